@@ -0,0 +1,55 @@
+//! Times `align_banded` against `align` on a text of about 10k tokens with a handful of edits. Run
+//! with `cargo bench --bench banded`.
+
+use std::time::{Duration, Instant};
+
+use platypus_diff::alignment::align_banded;
+use platypus_diff::{align, AffineScoring, Tokens};
+
+/// Best time of a few runs of `f`.
+fn time(mut f: impl FnMut()) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let lines: Vec<String> = (0..1000)
+        .map(|i| format!("let value{} = compute(value{}, {});\n", i, i / 2, i % 7))
+        .collect();
+    let left = lines.concat();
+    let mut edited = lines.clone();
+    edited[100] = "let inserted = 1;\n".to_string() + &edited[100];
+    edited[300] = edited[300].replace("compute", "recompute");
+    edited.remove(500);
+    edited[700] = edited[700].replace(", ", ", 2, ");
+    edited[900] = "// comment\n".to_string();
+    let right = edited.concat();
+    let (left, right) = (Tokens::new(&left), Tokens::new(&right));
+    let scoring = AffineScoring::default();
+    println!("{} and {} tokens", left.tokens.len(), right.tokens.len());
+
+    let full = time(|| {
+        align(&scoring, &left.tokens, &right.tokens);
+    });
+    println!("align:              {:>8.1} ms", full.as_secs_f64() * 1000.);
+    let expected = align(&scoring, &left.tokens, &right.tokens).score();
+    for band in [8, 64] {
+        let alignment = align_banded(&scoring, &left.tokens, &right.tokens, band);
+        assert_eq!(alignment.score(), expected);
+        let banded = time(|| {
+            align_banded(&scoring, &left.tokens, &right.tokens, band);
+        });
+        println!(
+            "align_banded({:>2}):   {:>8.1} ms ({:.0}x faster)",
+            band,
+            banded.as_secs_f64() * 1000.,
+            full.as_secs_f64() / banded.as_secs_f64()
+        );
+    }
+}
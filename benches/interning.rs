@@ -0,0 +1,65 @@
+//! Times aligning tokens compared by their texts against tokens compared by interned symbols, on
+//! a text with a small vocabulary of long identifiers repeated many times. Run with
+//! `cargo bench --bench interning`.
+
+use std::time::{Duration, Instant};
+
+use platypus_diff::interner::Interner;
+use platypus_diff::{align, AffineScoring, Tokens};
+
+/// Best time of a few runs of `f`.
+fn time(mut f: impl FnMut()) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    // Identifiers sharing long prefixes, which are the slowest to compare by text.
+    let words = [
+        "configuration_value_first",
+        "configuration_value_second",
+        "configuration_value_third",
+        "configuration_builder",
+        "configuration_error",
+    ];
+    let line = |i: usize| {
+        format!(
+            "{} = {}({});\n",
+            words[i % 5],
+            words[i * 3 % 5],
+            words[i * 7 % 5]
+        )
+    };
+    let left: String = (0..400).map(line).collect();
+    let right: String = (0..400).map(|i| line(i * 11 % 13 + i)).collect();
+    let (mut left, mut right) = (Tokens::new(&left), Tokens::new(&right));
+    let scoring = AffineScoring::default();
+    println!("{} and {} tokens", left.tokens.len(), right.tokens.len());
+
+    let plain = time(|| {
+        align(&scoring, &left.tokens, &right.tokens);
+    });
+    let expected = align(&scoring, &left.tokens, &right.tokens).score();
+    let mut interner = Interner::default();
+    interner.intern_tokens(&mut left.tokens);
+    interner.intern_tokens(&mut right.tokens);
+    assert_eq!(
+        align(&scoring, &left.tokens, &right.tokens).score(),
+        expected
+    );
+    let interned = time(|| {
+        align(&scoring, &left.tokens, &right.tokens);
+    });
+    println!("texts:   {:>8.1} ms", plain.as_secs_f64() * 1000.);
+    println!(
+        "symbols: {:>8.1} ms ({:.1}x faster)",
+        interned.as_secs_f64() * 1000.,
+        plain.as_secs_f64() / interned.as_secs_f64()
+    );
+}
@@ -0,0 +1,101 @@
+//! Counts the allocations of aligning with `ignore_case`, comparing the lowercase texts cached in
+//! the tokens with lowercasing both tokens for every DP cell, as `mutation_score` used to. Run with
+//! `cargo bench --bench lowercase`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use platypus_diff::types::{AlignmentScoring, Token as _};
+use platypus_diff::{align, AffineScoring, Token, TokenType, Tokens};
+
+/// System allocator counting the allocations.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// `AffineScoring` with `ignore_case`, but lowercasing the texts of both tokens for every pair.
+struct PerCellLowercase(AffineScoring);
+
+impl<'a> AlignmentScoring<Token<'a, TokenType>> for PerCellLowercase {
+    fn insert_score(&self, inserted: &Token<'a, TokenType>, previous_is_same: bool) -> f64 {
+        self.0.insert_score(inserted, previous_is_same)
+    }
+
+    fn mutation_score(
+        &self,
+        left: &Token<'a, TokenType>,
+        right: &Token<'a, TokenType>,
+        previous_is_same: bool,
+    ) -> f64 {
+        if left.t == right.t
+            && left.text() != right.text()
+            && left.text().to_lowercase() == right.text().to_lowercase()
+        {
+            self.0.mismatched_case_penalty
+        } else {
+            self.0.mutation_score(left, right, previous_is_same)
+        }
+    }
+}
+
+/// Allocations and milliseconds taken by `f`.
+fn measure(f: impl FnOnce()) -> (usize, f64) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    f();
+    (
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        start.elapsed().as_secs_f64() * 1000.,
+    )
+}
+
+fn main() {
+    let words = [
+        "Value", "compute", "Result", "OK", "Err", "self", "Self", "NEW",
+    ];
+    let line = |i: usize| {
+        format!(
+            "{} = {}({}, {});\n",
+            words[i % 8],
+            words[i * 3 % 8],
+            words[i * 5 % 8],
+            i % 13
+        )
+    };
+    let left: String = (0..300).map(line).collect();
+    let right: String = (0..300).map(|i| line(i).to_uppercase()).collect();
+    let (left, right) = (Tokens::new(&left), Tokens::new(&right));
+    println!("{} and {} tokens", left.tokens.len(), right.tokens.len());
+
+    let scoring = AffineScoring::default().with_ignore_case(true);
+    let per_cell = PerCellLowercase(scoring.clone());
+    let (before, before_ms) = measure(|| {
+        align(&per_cell, &left.tokens, &right.tokens);
+    });
+    let (after, after_ms) = measure(|| {
+        align(&scoring, &left.tokens, &right.tokens);
+    });
+    println!(
+        "lowercase per cell: {:>9} allocations, {:>7.1} ms",
+        before, before_ms
+    );
+    println!(
+        "cached lowercase:   {:>9} allocations, {:>7.1} ms",
+        after, after_ms
+    );
+}
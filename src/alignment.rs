@@ -1,225 +1,1282 @@
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::ops::Range;
 
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
+use regex::Regex;
+use serde::Serialize;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
+use crate::cleanup::same;
 use crate::types::{AlignmentScoring, Token};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
 pub enum AlignmentOperation<T> {
     Mutation { left: T, right: T },
     InsertLeft { left: T },
     InsertRight { right: T },
 }
 
-#[derive(Debug, Clone)]
-pub enum PathList<T> {
-    End,
-    Node {
-        payload: T,
-        previous: Rc<PathList<T>>,
+/// One of the three states of an alignment cell, i.e. what the last operation of a path was.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LastOperation {
+    Mutation,
+    InsertLeft,
+    InsertRight,
+}
+
+/// Picks the predecessor state with the lowest score. Ties are broken the same way everywhere, so
+/// that all alignment variants produce identical paths.
+#[allow(clippy::collapsible_else_if)]
+fn pick_best_predecessor(
+    mutation_score: f64,
+    insert_left_score: f64,
+    insert_right_score: f64,
+) -> (f64, LastOperation) {
+    if insert_left_score < insert_right_score {
+        if insert_left_score < mutation_score {
+            (insert_left_score, LastOperation::InsertLeft)
+        } else {
+            (mutation_score, LastOperation::Mutation)
+        }
+    } else {
+        if insert_right_score < mutation_score {
+            (insert_right_score, LastOperation::InsertRight)
+        } else {
+            (mutation_score, LastOperation::Mutation)
+        }
+    }
+}
+
+/// Picks the state of the last cell the alignment ends in.
+#[allow(clippy::collapsible_else_if)]
+fn pick_best_final(
+    mutation_score: f64,
+    insert_left_score: f64,
+    insert_right_score: f64,
+) -> LastOperation {
+    if mutation_score < insert_left_score {
+        if mutation_score < insert_right_score {
+            LastOperation::Mutation
+        } else {
+            LastOperation::InsertRight
+        }
+    } else {
+        if insert_left_score < insert_right_score {
+            LastOperation::InsertLeft
+        } else {
+            LastOperation::InsertRight
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AlignmentError {
+    MemoryBudgetExceeded {
+        /// `None` when the estimate itself overflows.
+        estimated: Option<usize>,
+        budget: usize,
     },
 }
 
-impl<T: Clone> PathList<T> {
-    pub fn extract_path(self) -> Vec<T> {
-        let mut out = vec![];
-        let mut current = self;
-        loop {
-            current = match current {
-                PathList::End => break,
-                PathList::Node { payload, previous } => {
-                    out.push(payload);
-                    Rc::try_unwrap(previous).unwrap_or_else(|x| {
-                        eprintln!("More than 1 reference!");
-                        (*x).clone()
-                    })
+impl std::fmt::Display for AlignmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlignmentError::MemoryBudgetExceeded { estimated, budget } => {
+                let mib = |bytes: usize| bytes.div_ceil(1024 * 1024);
+                match estimated {
+                    Some(estimated) => write!(
+                        f,
+                        "alignment would need about {} MiB, which exceeds the memory budget of {} MiB",
+                        mib(*estimated),
+                        mib(*budget)
+                    ),
+                    None => write!(
+                        f,
+                        "alignment would need more memory than can be addressed (budget is {} MiB)",
+                        mib(*budget)
+                    ),
                 }
             }
         }
-        out.reverse();
-        out
     }
 }
 
-struct AlignmentData<'a, T> {
-    score: f64,
-    path: Rc<PathList<AlignmentOperation<&'a T>>>,
+/// Rough upper estimate of the memory `align` needs: two rows of scores, plus back pointers for
+/// every DP cell. Returns `None` on overflow.
+pub fn estimate_memory(left_len: usize, right_len: usize) -> Option<usize> {
+    let rows = (left_len.checked_add(1)?).checked_mul(2 * std::mem::size_of::<ScoreState>())?;
+    let pointers = (left_len.checked_add(1)?)
+        .checked_mul(right_len.checked_add(1)?)?
+        .checked_mul(std::mem::size_of::<BackPointers>())?;
+    rows.checked_add(pointers)
 }
 
-impl<'a, T> AlignmentData<'a, T> {
-    pub fn new() -> Self {
-        Self {
-            score: 0.,
-            path: Rc::new(PathList::End),
+/// Rough upper estimate of the memory `align_low_memory` needs: checkpoint rows and one block of
+/// recomputed rows of scores. Returns `None` on overflow.
+pub fn estimate_low_memory(left_len: usize, right_len: usize) -> Option<usize> {
+    let block = ((right_len.checked_add(1)?) as f64).sqrt().ceil() as usize;
+    let rows = (right_len.checked_add(1)?)
+        .div_ceil(block)
+        .checked_add(block)?;
+    rows.checked_mul(left_len.checked_add(1)?)?
+        .checked_mul(std::mem::size_of::<ScoreState>())
+}
+
+/// Rough upper estimate of the memory `align_linear` needs: rows of scores and of middle row
+/// cells on one level of the recursion. Returns `None` on overflow.
+pub fn estimate_linear_memory(left_len: usize) -> Option<usize> {
+    let cell = 2 * std::mem::size_of::<ScoreState>()
+        + 2 * std::mem::size_of::<[(usize, LastOperation); 3]>()
+        + 2 * std::mem::size_of::<BackPointers>();
+    (left_len.checked_add(1)?).checked_mul(cell)
+}
+
+/// Checks that the `estimated` memory fits into `budget` bytes, before anything is allocated.
+pub fn check_memory_budget(estimated: Option<usize>, budget: usize) -> Result<(), AlignmentError> {
+    match estimated {
+        Some(estimated) if estimated <= budget => Ok(()),
+        estimated => Err(AlignmentError::MemoryBudgetExceeded { estimated, budget }),
+    }
+}
+
+/// Best predecessor state of each of the three states of a DP cell, for the traceback.
+#[derive(Clone, Copy)]
+struct BackPointers {
+    mutation: LastOperation,
+    insert_left: LastOperation,
+    insert_right: LastOperation,
+}
+
+impl BackPointers {
+    /// Pointers of cells where only one state is reachable, so the others are never followed.
+    fn only(state: LastOperation, predecessor: LastOperation) -> Self {
+        let mut pointers = BackPointers {
+            mutation: LastOperation::Mutation,
+            insert_left: LastOperation::Mutation,
+            insert_right: LastOperation::Mutation,
+        };
+        *pointers.get_mut(state) = predecessor;
+        pointers
+    }
+
+    fn get(&self, state: LastOperation) -> LastOperation {
+        match state {
+            LastOperation::Mutation => self.mutation,
+            LastOperation::InsertLeft => self.insert_left,
+            LastOperation::InsertRight => self.insert_right,
         }
     }
-    pub fn unreachable() -> Self {
-        Self {
-            score: f64::INFINITY,
-            path: Rc::new(PathList::End),
+
+    fn get_mut(&mut self, state: LastOperation) -> &mut LastOperation {
+        match state {
+            LastOperation::Mutation => &mut self.mutation,
+            LastOperation::InsertLeft => &mut self.insert_left,
+            LastOperation::InsertRight => &mut self.insert_right,
         }
     }
 }
 
-struct AlignmentState<'a, T> {
-    last_was_mutation: AlignmentData<'a, T>,
-    last_was_insert_left: AlignmentData<'a, T>,
-    last_was_insert_right: AlignmentData<'a, T>,
+/// Scores of the three states of a DP cell, i.e. of the best paths ending in the cell with each
+/// kind of operation.
+#[derive(Clone, Copy)]
+struct ScoreState {
+    last_was_mutation: f64,
+    last_was_insert_left: f64,
+    last_was_insert_right: f64,
+    /// Lengths of the runs of inserts the best paths in the insert states end with, for gap costs
+    /// depending on the run length.
+    insert_left_run: u32,
+    insert_right_run: u32,
+    /// Whether the best path in the mutation state ends with a match, i.e. a mutation costing
+    /// nothing, as only matches are extended by a following match.
+    mutation_matched: bool,
 }
 
-impl<'a, T> AlignmentState<'a, T> {
-    #[allow(clippy::collapsible_else_if)]
-    pub fn pick_best(
-        &self,
-        payload: AlignmentOperation<&'a T>,
-        mutation_score: f64,
-        insert_left_score: f64,
-        insert_right_score: f64,
-    ) -> AlignmentData<'a, T> {
-        let (score, previous) = if insert_left_score < insert_right_score {
-            if insert_left_score < mutation_score {
-                (insert_left_score, self.last_was_insert_left.path.clone())
-            } else {
-                (mutation_score, self.last_was_mutation.path.clone())
-            }
-        } else {
-            if insert_right_score < mutation_score {
-                (insert_right_score, self.last_was_insert_right.path.clone())
-            } else {
-                (mutation_score, self.last_was_mutation.path.clone())
-            }
-        };
-        AlignmentData {
-            score,
-            path: Rc::new(PathList::Node { payload, previous }),
+impl ScoreState {
+    pub fn start() -> Self {
+        Self::only(LastOperation::Mutation, 0.)
+    }
+
+    /// Cell reached only in `state`, with the given score.
+    fn only(state: LastOperation, score: f64) -> Self {
+        let score = |x: LastOperation| if x == state { score } else { f64::INFINITY };
+        Self {
+            last_was_mutation: score(LastOperation::Mutation),
+            last_was_insert_left: score(LastOperation::InsertLeft),
+            last_was_insert_right: score(LastOperation::InsertRight),
+            insert_left_run: (state == LastOperation::InsertLeft).into(),
+            insert_right_run: (state == LastOperation::InsertRight).into(),
+            mutation_matched: false,
         }
     }
 
-    #[allow(clippy::collapsible_else_if)]
-    pub fn extract_best(self) -> AlignmentData<'a, T> {
-        if self.last_was_mutation.score < self.last_was_insert_left.score {
-            if self.last_was_mutation.score < self.last_was_insert_right.score {
-                self.last_was_mutation
-            } else {
-                self.last_was_insert_right
-            }
-        } else {
-            if self.last_was_insert_left.score < self.last_was_insert_right.score {
-                self.last_was_insert_left
-            } else {
-                self.last_was_insert_right
-            }
+    fn unreachable() -> Self {
+        Self {
+            last_was_mutation: f64::INFINITY,
+            last_was_insert_left: f64::INFINITY,
+            last_was_insert_right: f64::INFINITY,
+            insert_left_run: 0,
+            insert_right_run: 0,
+            mutation_matched: false,
         }
     }
 
-    pub fn insert_left_score<S: AlignmentScoring<T>>(
+    fn get(&self, state: LastOperation) -> f64 {
+        match state {
+            LastOperation::Mutation => self.last_was_mutation,
+            LastOperation::InsertLeft => self.last_was_insert_left,
+            LastOperation::InsertRight => self.last_was_insert_right,
+        }
+    }
+
+    pub fn best(&self) -> f64 {
+        self.last_was_mutation
+            .min(self.last_was_insert_left)
+            .min(self.last_was_insert_right)
+    }
+
+    /// Scores of reaching the insert left state from each state of this cell.
+    pub fn insert_left_candidates<T, S: AlignmentScoring<T>>(
         &self,
         scoring: &S,
-        l: &'a T,
-    ) -> AlignmentData<'a, T> {
-        let mutation_score = self.last_was_mutation.score + scoring.insert_score(l, false);
-        let insert_left_score = self.last_was_insert_left.score + scoring.insert_score(l, true);
-        let insert_right_score = self.last_was_insert_right.score + scoring.insert_score(l, false);
-        self.pick_best(
-            AlignmentOperation::InsertLeft { left: l },
-            mutation_score,
-            insert_left_score,
-            insert_right_score,
+        l: &T,
+    ) -> (f64, f64, f64) {
+        let start = scoring.insert_run_score(l, 1);
+        (
+            self.last_was_mutation + start,
+            self.last_was_insert_left
+                + scoring.insert_run_score(l, self.insert_left_run as usize + 1),
+            self.last_was_insert_right + start,
         )
     }
 
-    pub fn insert_right_score<S: AlignmentScoring<T>>(
+    pub fn insert_right_candidates<T, S: AlignmentScoring<T>>(
         &self,
         scoring: &S,
-        r: &'a T,
-    ) -> AlignmentData<'a, T> {
-        let mutation_score = self.last_was_mutation.score + scoring.insert_score(r, false);
-        let insert_left_score = self.last_was_insert_left.score + scoring.insert_score(r, false);
-        let insert_right_score = self.last_was_insert_right.score + scoring.insert_score(r, true);
-        self.pick_best(
-            AlignmentOperation::InsertRight { right: r },
-            mutation_score,
-            insert_left_score,
-            insert_right_score,
+        r: &T,
+    ) -> (f64, f64, f64) {
+        let start = scoring.insert_run_score(r, 1);
+        (
+            self.last_was_mutation + start,
+            self.last_was_insert_left + start,
+            self.last_was_insert_right
+                + scoring.insert_run_score(r, self.insert_right_run as usize + 1),
         )
     }
 
-    pub fn mutation_score<S: AlignmentScoring<T>>(
+    pub fn mutation_candidates<T, S: AlignmentScoring<T>>(
         &self,
         scoring: &S,
-        l: &'a T,
-        r: &'a T,
-    ) -> AlignmentData<'a, T> {
-        let s = scoring.mutation_score(l, r);
-        let mutation_score = self.last_was_mutation.score + s;
-        let insert_left_score = self.last_was_insert_left.score + s;
-        let insert_right_score = self.last_was_insert_right.score + s;
-        self.pick_best(
-            AlignmentOperation::Mutation { left: l, right: r },
-            mutation_score,
-            insert_left_score,
-            insert_right_score,
+        l: &T,
+        r: &T,
+    ) -> (f64, f64, f64) {
+        let s = scoring.mutation_score(l, r, false);
+        (
+            self.last_was_mutation + scoring.mutation_score(l, r, self.mutation_matched),
+            self.last_was_insert_left + s,
+            self.last_was_insert_right + s,
         )
     }
-}
 
-type AlignmentLineDS<'a, T> = Vec<AlignmentState<'a, T>>;
+    fn pick((mutation, insert_left, insert_right): (f64, f64, f64)) -> (f64, LastOperation) {
+        pick_best_predecessor(mutation, insert_left, insert_right)
+    }
+
+    /// Best score of reaching the mutation state from this cell, its predecessor state and whether
+    /// the mutation is a match.
+    fn mutation<T, S: AlignmentScoring<T>>(
+        &self,
+        scoring: &S,
+        l: &T,
+        r: &T,
+    ) -> (f64, LastOperation, bool) {
+        let (score, from) = Self::pick(self.mutation_candidates(scoring, l, r));
+        (score, from, scoring.mutation_score(l, r, false) <= 0.)
+    }
+
+    /// Best score of reaching the insert left state from this cell, its predecessor state and the
+    /// length of the resulting run of inserts.
+    fn insert_left<T, S: AlignmentScoring<T>>(
+        &self,
+        scoring: &S,
+        l: &T,
+    ) -> (f64, LastOperation, u32) {
+        let (score, from) = Self::pick(self.insert_left_candidates(scoring, l));
+        let run = match from {
+            LastOperation::InsertLeft => self.insert_left_run + 1,
+            _ => 1,
+        };
+        (score, from, run)
+    }
+
+    /// Same as `insert_left` for the insert right state.
+    fn insert_right<T, S: AlignmentScoring<T>>(
+        &self,
+        scoring: &S,
+        r: &T,
+    ) -> (f64, LastOperation, u32) {
+        let (score, from) = Self::pick(self.insert_right_candidates(scoring, r));
+        let run = match from {
+            LastOperation::InsertRight => self.insert_right_run + 1,
+            _ => 1,
+        };
+        (score, from, run)
+    }
+
+    /// First row of scores, before any right token.
+    pub fn first_row<T, S: AlignmentScoring<T>>(scoring: &S, left: &[T]) -> Vec<Self> {
+        Self::first_row_traced(scoring, left, Self::start(), |_| {})
+    }
+
+    /// Same as `first_row` for paths starting in cell `start`, passing back pointers of every
+    /// cell to `trace`.
+    fn first_row_traced<T, S: AlignmentScoring<T>>(
+        scoring: &S,
+        left: &[T],
+        start: ScoreState,
+        mut trace: impl FnMut(BackPointers),
+    ) -> Vec<Self> {
+        let mut row: Vec<ScoreState> = Vec::with_capacity(left.len() + 1);
+        row.push(start);
+        trace(BackPointers::only(
+            LastOperation::Mutation,
+            LastOperation::Mutation,
+        ));
+        for l in left.iter() {
+            let prev = row.last().unwrap();
+            let (insert_left, from, run) = prev.insert_left(scoring, l);
+            row.push(ScoreState {
+                last_was_insert_left: insert_left,
+                insert_left_run: run,
+                ..ScoreState::unreachable()
+            });
+            trace(BackPointers::only(LastOperation::InsertLeft, from));
+        }
+        row
+    }
+
+    /// Row of scores after right token `r`, computed from the previous row into `next`.
+    pub fn next_row<T, S: AlignmentScoring<T>>(
+        scoring: &S,
+        left: &[T],
+        r: &T,
+        current: &[Self],
+        next: &mut Vec<Self>,
+    ) {
+        Self::next_row_traced(scoring, left, r, current, next, |_| {})
+    }
+
+    /// Same as `next_row`, passing back pointers of every cell to `trace`.
+    fn next_row_traced<T, S: AlignmentScoring<T>>(
+        scoring: &S,
+        left: &[T],
+        r: &T,
+        current: &[Self],
+        next: &mut Vec<Self>,
+        mut trace: impl FnMut(BackPointers),
+    ) {
+        next.clear();
+        let (insert_right, from, run) = current[0].insert_right(scoring, r);
+        next.push(ScoreState {
+            last_was_insert_right: insert_right,
+            insert_right_run: run,
+            ..ScoreState::unreachable()
+        });
+        trace(BackPointers::only(LastOperation::InsertRight, from));
+        for (l_index, l) in left.iter().enumerate() {
+            let l_index = l_index + 1;
+            let mutation = current[l_index - 1].mutation(scoring, l, r);
+            let insert_left = next[l_index - 1].insert_left(scoring, l);
+            let insert_right = current[l_index].insert_right(scoring, r);
+            next.push(ScoreState {
+                last_was_mutation: mutation.0,
+                last_was_insert_left: insert_left.0,
+                last_was_insert_right: insert_right.0,
+                insert_left_run: insert_left.2,
+                insert_right_run: insert_right.2,
+                mutation_matched: mutation.2,
+            });
+            trace(BackPointers {
+                mutation: mutation.1,
+                insert_left: insert_left.1,
+                insert_right: insert_right.1,
+            });
+        }
+    }
+}
 
 pub fn align<'a, T, S: AlignmentScoring<T>>(
     scoring: &S,
     left: &'a [T],
     right: &'a [T],
 ) -> Alignment<'a, T> {
-    let result_path = {
-        let mut current: AlignmentLineDS<'a, T> = Vec::with_capacity(left.len() + 1);
-        current.push(AlignmentState {
-            last_was_mutation: AlignmentData::new(),
-            last_was_insert_left: AlignmentData::unreachable(),
-            last_was_insert_right: AlignmentData::unreachable(),
-        });
-        for l in left.iter() {
-            let prev = current.last().unwrap();
-            current.push(AlignmentState {
-                last_was_mutation: AlignmentData::unreachable(),
-                last_was_insert_left: prev.insert_left_score(scoring, l),
-                last_was_insert_right: AlignmentData::unreachable(),
-            })
+    align_with_stats(scoring, left, right, &mut ())
+}
+
+/// Receives counts of the work done by the aligners. The implementation for `()` does nothing, so
+/// collecting statistics costs nothing when they are not needed.
+pub trait StatsCollector {
+    /// A row of DP cells was computed.
+    fn row(&mut self, _cells: usize) {}
+    /// Back pointers were stored for the traceback.
+    fn back_pointers(&mut self, _count: usize) {}
+}
+
+impl StatsCollector for () {}
+
+/// Work done by the aligners, for performance tuning.
+#[derive(Debug, Default, Clone)]
+pub struct AlignmentStats {
+    /// Number of evaluated DP cells, each with three states.
+    pub cells: usize,
+    pub back_pointers: usize,
+    /// Largest number of cells in a row.
+    pub peak_row: usize,
+}
+
+impl StatsCollector for AlignmentStats {
+    fn row(&mut self, cells: usize) {
+        self.cells += cells;
+        self.peak_row = self.peak_row.max(cells);
+    }
+
+    fn back_pointers(&mut self, count: usize) {
+        self.back_pointers += count;
+    }
+}
+
+/// Same as `align`, reporting the work done to `stats`.
+pub fn align_with_stats<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    stats: &mut C,
+) -> Alignment<'a, T> {
+    if left.is_empty() || right.is_empty() {
+        // All tokens are inserts, so there is nothing to search.
+        return align_identical(scoring, left, right);
+    }
+    let (operations, score) = traced_path(scoring, left, right, ScoreState::start(), None, stats);
+    Alignment {
+        operations,
+        score,
+        offsets: vec![],
+    }
+}
+
+/// Best path from the `start` cell to the end cell, in state `end` if given, and its score. Back
+/// pointers of all cells are kept for the traceback.
+fn traced_path<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    start: ScoreState,
+    end: Option<LastOperation>,
+    stats: &mut C,
+) -> (Vec<AlignmentOperation<&'a T>>, f64) {
+    let (pointers, last) = back_pointers(scoring, left, right, start, stats);
+    let state = end.unwrap_or_else(|| {
+        pick_best_final(
+            last.last_was_mutation,
+            last.last_was_insert_left,
+            last.last_was_insert_right,
+        )
+    });
+    let width = left.len() + 1;
+    let operations = trace_back(left, right, state, |i, j| Some(pointers[i * width + j]));
+    (operations.unwrap(), last.best())
+}
+
+/// Back pointers of all cells, row after row, and the scores of the end cell. The traceback only
+/// reads them, so any number of paths can be traced back through the same matrix.
+fn back_pointers<T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &[T],
+    right: &[T],
+    start: ScoreState,
+    stats: &mut C,
+) -> (Vec<BackPointers>, ScoreState) {
+    let width = left.len() + 1;
+    let mut pointers = Vec::with_capacity(width * (right.len() + 1));
+    let mut current = ScoreState::first_row_traced(scoring, left, start, |x| pointers.push(x));
+    stats.row(current.len());
+    stats.back_pointers(current.len());
+    let mut next = Vec::with_capacity(width);
+    for r in right.iter() {
+        ScoreState::next_row_traced(scoring, left, r, &current, &mut next, |x| pointers.push(x));
+        stats.row(next.len());
+        stats.back_pointers(next.len());
+        std::mem::swap(&mut current, &mut next);
+    }
+    (pointers, current[left.len()])
+}
+
+/// Operations of the path ending in the last cell in state `state`, following the back pointers
+/// of cell `(i, j)` (after `i` right and `j` left tokens) given by `pointers`. Stops with `None`
+/// when `pointers` gives none.
+fn trace_back<'a, T>(
+    left: &'a [T],
+    right: &'a [T],
+    mut state: LastOperation,
+    mut pointers: impl FnMut(usize, usize) -> Option<BackPointers>,
+) -> Option<Vec<AlignmentOperation<&'a T>>> {
+    let mut operations = Vec::with_capacity(left.len().max(right.len()));
+    let (mut i, mut j) = (right.len(), left.len());
+    while i > 0 || j > 0 {
+        let previous = pointers(i, j)?.get(state);
+        match state {
+            LastOperation::Mutation => {
+                operations.push(AlignmentOperation::Mutation {
+                    left: &left[j - 1],
+                    right: &right[i - 1],
+                });
+                i -= 1;
+                j -= 1;
+            }
+            LastOperation::InsertLeft => {
+                operations.push(AlignmentOperation::InsertLeft { left: &left[j - 1] });
+                j -= 1;
+            }
+            LastOperation::InsertRight => {
+                operations.push(AlignmentOperation::InsertRight {
+                    right: &right[i - 1],
+                });
+                i -= 1;
+            }
         }
-        let mut next = Vec::with_capacity(left.len() + 1);
-        for r in right.iter() {
-            let prev = &current[0];
-            next.push(AlignmentState {
-                last_was_mutation: AlignmentData::unreachable(),
-                last_was_insert_left: AlignmentData::unreachable(),
-                last_was_insert_right: prev.insert_right_score(scoring, r),
+        state = previous;
+    }
+    operations.reverse();
+    Some(operations)
+}
+
+/// Computes the same alignment as `align` in memory linear in the length of `left`, using
+/// Hirschberg's divide and conquer. Instead of a backward pass, which could pick a different path
+/// among equally good ones, the forward pass through the lower half of the rows carries for every
+/// cell the cell of the middle row its traceback leads to. The halves above and below that cell
+/// are then aligned recursively. This takes about twice the time of `align`.
+pub fn align_linear<'a, T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+) -> Alignment<'a, T> {
+    align_linear_with_stats(scoring, left, right, &mut ())
+}
+
+/// Same as `align_linear`, reporting the work done to `stats`.
+pub fn align_linear_with_stats<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    stats: &mut C,
+) -> Alignment<'a, T> {
+    if left.is_empty() || right.is_empty() {
+        return align_identical(scoring, left, right);
+    }
+    let mut current = ScoreState::first_row(scoring, left);
+    stats.row(current.len());
+    let mut next = Vec::with_capacity(left.len() + 1);
+    for r in right.iter() {
+        ScoreState::next_row(scoring, left, r, &current, &mut next);
+        stats.row(next.len());
+        std::mem::swap(&mut current, &mut next);
+    }
+    let last = current[left.len()];
+    let end = pick_best_final(
+        last.last_was_mutation,
+        last.last_was_insert_left,
+        last.last_was_insert_right,
+    );
+    let mut operations = Vec::with_capacity(left.len().max(right.len()));
+    linear_path(
+        scoring,
+        left,
+        right,
+        ScoreState::start(),
+        end,
+        &mut operations,
+        stats,
+    );
+    Alignment {
+        operations,
+        score: last.best(),
+        offsets: vec![],
+    }
+}
+
+/// Appends the path `align` would trace from the `start` cell to the end cell in state `end`.
+/// Cells outside of the path may score worse than in the whole alignment, as the subproblem sees
+/// only a part of it, but never better. Cells on the path score exactly the same, as the start
+/// cell has its score from the whole alignment, so they pick the same predecessors.
+#[allow(clippy::too_many_arguments)]
+fn linear_path<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    start: ScoreState,
+    end: LastOperation,
+    operations: &mut Vec<AlignmentOperation<&'a T>>,
+    stats: &mut C,
+) {
+    if right.len() < 2 {
+        operations.extend(traced_path(scoring, left, right, start, Some(end), stats).0);
+        return;
+    }
+    let middle_index = right.len() / 2;
+    let mut current = ScoreState::first_row_traced(scoring, left, start, |_| {});
+    stats.row(current.len());
+    let mut next = Vec::with_capacity(left.len() + 1);
+    for r in right[..middle_index].iter() {
+        ScoreState::next_row(scoring, left, r, &current, &mut next);
+        stats.row(next.len());
+        std::mem::swap(&mut current, &mut next);
+    }
+    let middle_row = current.clone();
+    // For every cell and state, the column and state in the middle row its traceback leads to.
+    let states = [
+        LastOperation::Mutation,
+        LastOperation::InsertLeft,
+        LastOperation::InsertRight,
+    ];
+    let mut labels: Vec<[(usize, LastOperation); 3]> = (0..=left.len())
+        .map(|column| states.map(|state| (column, state)))
+        .collect();
+    let mut next_labels = Vec::with_capacity(left.len() + 1);
+    for r in right[middle_index..].iter() {
+        next_labels.clear();
+        ScoreState::next_row_traced(scoring, left, r, &current, &mut next, |pointers| {
+            let column = next_labels.len();
+            let label = |row: &[[(usize, LastOperation); 3]], column: usize, state| {
+                row[column][state as usize]
+            };
+            next_labels.push(if column == 0 {
+                // Only the insert right state is reachable in the first column.
+                [label(&labels, 0, pointers.insert_right); 3]
+            } else {
+                [
+                    label(&labels, column - 1, pointers.mutation),
+                    label(&next_labels, column - 1, pointers.insert_left),
+                    label(&labels, column, pointers.insert_right),
+                ]
             });
-            for (l_index, l) in left.iter().enumerate() {
-                let l_index = l_index + 1;
-                next.push(AlignmentState {
-                    last_was_mutation: current[l_index - 1].mutation_score(scoring, l, r),
-                    last_was_insert_left: next[l_index - 1].insert_left_score(scoring, l),
-                    last_was_insert_right: current[l_index].insert_right_score(scoring, r),
-                });
+        });
+        stats.row(next.len());
+        std::mem::swap(&mut current, &mut next);
+        std::mem::swap(&mut labels, &mut next_labels);
+    }
+    let (column, state) = labels[left.len()][end as usize];
+    let middle = ScoreState {
+        insert_left_run: middle_row[column].insert_left_run,
+        insert_right_run: middle_row[column].insert_right_run,
+        mutation_matched: middle_row[column].mutation_matched,
+        ..ScoreState::only(state, middle_row[column].get(state))
+    };
+    drop(middle_row);
+    drop(labels);
+    drop(next_labels);
+    drop(current);
+    drop(next);
+    linear_path(
+        scoring,
+        &left[..column],
+        &right[..middle_index],
+        start,
+        state,
+        operations,
+        stats,
+    );
+    linear_path(
+        scoring,
+        &left[column..],
+        &right[middle_index..],
+        middle,
+        end,
+        operations,
+        stats,
+    );
+}
+
+/// Computes the score `align` would reach, keeping only two rows of scores instead of the back
+/// pointers.
+pub fn score_only<T, S: AlignmentScoring<T>>(scoring: &S, left: &[T], right: &[T]) -> f64 {
+    let mut current = ScoreState::first_row(scoring, left);
+    let mut next = Vec::with_capacity(left.len() + 1);
+    for r in right.iter() {
+        ScoreState::next_row(scoring, left, r, &current, &mut next);
+        std::mem::swap(&mut current, &mut next);
+    }
+    current.last().unwrap().best()
+}
+
+/// Computes the same alignment as `align` on multiple threads. Cells on the same anti-diagonal
+/// don't depend on each other, so each anti-diagonal is computed in parallel from the previous two.
+#[cfg(feature = "parallel")]
+pub fn align_parallel<'a, T: Sync, S: AlignmentScoring<T> + Sync>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+) -> Alignment<'a, T> {
+    align_parallel_with_stats(scoring, left, right, &mut ())
+}
+
+/// Same as `align_parallel`, reporting the work done to `stats`.
+#[cfg(feature = "parallel")]
+pub fn align_parallel_with_stats<'a, T: Sync, S: AlignmentScoring<T> + Sync, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    stats: &mut C,
+) -> Alignment<'a, T> {
+    use rayon::prelude::*;
+
+    if left.is_empty() || right.is_empty() {
+        return align_identical(scoring, left, right);
+    }
+    // Anti-diagonals with fewer cells are computed on the current thread.
+    const MIN_CELLS_PER_TASK: usize = 256;
+    let width = left.len() + 1;
+    let mut pointers = vec![
+        BackPointers::only(LastOperation::Mutation, LastOperation::Mutation);
+        width * (right.len() + 1)
+    ];
+    // Cells of an anti-diagonal `d` are the cells `(i, d - i)` for `i` in `rows(d)`.
+    let rows = |d: usize| d.saturating_sub(left.len())..d.min(right.len()) + 1;
+    let cell = |diagonal: &[ScoreState], d: usize, i: usize| {
+        let rows = rows(d);
+        if rows.contains(&i) {
+            diagonal[i - rows.start]
+        } else {
+            ScoreState::unreachable()
+        }
+    };
+    let mut before_previous: Vec<ScoreState> = vec![];
+    let mut previous: Vec<ScoreState> = vec![ScoreState::start()];
+    stats.row(1);
+    stats.back_pointers(1);
+    for d in 1..=left.len() + right.len() {
+        let compute = |i: usize| {
+            let j = d - i;
+            let unreachable = (f64::INFINITY, LastOperation::Mutation, false);
+            let mutation = if i > 0 && j > 0 {
+                cell(&before_previous, d - 2, i - 1).mutation(scoring, &left[j - 1], &right[i - 1])
+            } else {
+                unreachable
+            };
+            let insert_left = if j > 0 {
+                cell(&previous, d - 1, i).insert_left(scoring, &left[j - 1])
+            } else {
+                (f64::INFINITY, LastOperation::Mutation, 0)
+            };
+            let insert_right = if i > 0 {
+                cell(&previous, d - 1, i - 1).insert_right(scoring, &right[i - 1])
+            } else {
+                (f64::INFINITY, LastOperation::Mutation, 0)
+            };
+            (
+                ScoreState {
+                    last_was_mutation: mutation.0,
+                    last_was_insert_left: insert_left.0,
+                    last_was_insert_right: insert_right.0,
+                    insert_left_run: insert_left.2,
+                    insert_right_run: insert_right.2,
+                    mutation_matched: mutation.2,
+                },
+                BackPointers {
+                    mutation: mutation.1,
+                    insert_left: insert_left.1,
+                    insert_right: insert_right.1,
+                },
+            )
+        };
+        let cells: Vec<_> = rows(d)
+            .into_par_iter()
+            .with_min_len(MIN_CELLS_PER_TASK)
+            .map(compute)
+            .collect();
+        stats.row(cells.len());
+        stats.back_pointers(cells.len());
+        let mut diagonal = Vec::with_capacity(cells.len());
+        for (i, (score, cell_pointers)) in rows(d).zip(cells) {
+            pointers[i * width + d - i] = cell_pointers;
+            diagonal.push(score);
+        }
+        before_previous = std::mem::replace(&mut previous, diagonal);
+    }
+    let last = *previous.last().unwrap();
+    let state = pick_best_final(
+        last.last_was_mutation,
+        last.last_was_insert_left,
+        last.last_was_insert_right,
+    );
+    let operations = trace_back(left, right, state, |i, j| Some(pointers[i * width + j]));
+    Alignment {
+        operations: operations.unwrap(),
+        score: last.best(),
+        offsets: vec![],
+    }
+}
+
+/// Computes an alignment only from the cells within `band` diagonals of the ones between the
+/// start and the end, which is much faster for inputs with few changes. The best path in the band
+/// is only taken when it doesn't touch the edge of the band and costs less than any path leaving
+/// the band could, see `leaving_band_cost`. Otherwise the band is doubled and the alignment
+/// retried, and once the band covers all cells, `align` is used. So the score is always the same
+/// as of `align`, assuming costs of runs of inserts don't grow with their length.
+pub fn align_banded<'a, T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    band: usize,
+) -> Alignment<'a, T> {
+    align_banded_with_stats(scoring, left, right, band, &mut ())
+}
+
+/// Same as `align_banded`, reporting the work done to `stats`.
+pub fn align_banded_with_stats<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    band: usize,
+    stats: &mut C,
+) -> Alignment<'a, T> {
+    if left.is_empty() || right.is_empty() {
+        return align_identical(scoring, left, right);
+    }
+    let mut band = band.max(1);
+    while band < left.len().max(right.len()) {
+        if let Some(alignment) = banded_path(scoring, left, right, band, stats) {
+            if alignment.score() < leaving_band_cost(scoring, left, right, band) {
+                return alignment;
             }
+        }
+        band = band.saturating_mul(2);
+    }
+    align_with_stats(scoring, left, right, stats)
+}
+
+/// Lower bound of the cost of paths which leave the band of `band` diagonals around the ones
+/// between the start and the end. Such a path inserts more than `band` tokens on each side, and
+/// has at most as many mutations as the shorter side has tokens. Costs of runs of inserts are
+/// assumed not to grow with their length, so the inserts on one side cost at least a run of
+/// `band + 1` of the cheapest tokens.
+fn leaving_band_cost<T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &[T],
+    right: &[T],
+    band: usize,
+) -> f64 {
+    let inserts = |tokens: &[T]| {
+        let cheapest = |run_length: usize| {
+            tokens
+                .iter()
+                .map(|token| scoring.insert_run_score(token, run_length))
+                .fold(f64::INFINITY, f64::min)
+        };
+        cheapest(1) + band as f64 * cheapest(band + 1)
+    };
+    let mutations = left.len().min(right.len()) as f64 * scoring.min_mutation_score();
+    inserts(left) + inserts(right) + mutations
+}
 
-            std::mem::swap(&mut current, &mut next);
-            next.clear()
+/// Best path among the cells within `band` diagonals, or `None` if it touches the edge of the band.
+fn banded_path<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    band: usize,
+    stats: &mut C,
+) -> Option<Alignment<'a, T>> {
+    // Columns of row `i` in the band, as diagonals `j - i` between the start and the end diagonal
+    // and `band` more on each side.
+    let (n, m) = (left.len() as isize, right.len() as isize);
+    let columns = |i: usize| {
+        let i = i as isize;
+        let first = (i + (n - m).min(0) - band as isize).max(0);
+        let last = (i + (n - m).max(0) + band as isize).min(n);
+        first as usize..last as usize + 1
+    };
+    let cell = |row: &[ScoreState], range: &Range<usize>, j: usize| {
+        if range.contains(&j) {
+            row[j - range.start]
+        } else {
+            ScoreState::unreachable()
         }
-        current.pop().unwrap().extract_best().path
     };
+    // Rows of scores and back pointers within the band.
+    let mut previous_range = 0..0;
+    let mut previous: Vec<ScoreState> = vec![];
+    let mut pointers: Vec<Vec<BackPointers>> = Vec::with_capacity(right.len() + 1);
+    for i in 0..=right.len() {
+        let range = columns(i);
+        let mut row: Vec<ScoreState> = Vec::with_capacity(range.len());
+        let mut row_pointers = Vec::with_capacity(range.len());
+        for j in range.clone() {
+            if i == 0 && j == 0 {
+                row.push(ScoreState::start());
+                row_pointers.push(BackPointers::only(
+                    LastOperation::Mutation,
+                    LastOperation::Mutation,
+                ));
+                continue;
+            }
+            let unreachable = (f64::INFINITY, LastOperation::Mutation, false);
+            let mutation = if i > 0 && j > 0 {
+                cell(&previous, &previous_range, j - 1).mutation(
+                    scoring,
+                    &left[j - 1],
+                    &right[i - 1],
+                )
+            } else {
+                unreachable
+            };
+            let insert_left = if j > 0 {
+                cell(&row, &(range.start..j), j - 1).insert_left(scoring, &left[j - 1])
+            } else {
+                (f64::INFINITY, LastOperation::Mutation, 0)
+            };
+            let insert_right = if i > 0 {
+                cell(&previous, &previous_range, j).insert_right(scoring, &right[i - 1])
+            } else {
+                (f64::INFINITY, LastOperation::Mutation, 0)
+            };
+            row.push(ScoreState {
+                last_was_mutation: mutation.0,
+                last_was_insert_left: insert_left.0,
+                last_was_insert_right: insert_right.0,
+                insert_left_run: insert_left.2,
+                insert_right_run: insert_right.2,
+                mutation_matched: mutation.2,
+            });
+            row_pointers.push(BackPointers {
+                mutation: mutation.1,
+                insert_left: insert_left.1,
+                insert_right: insert_right.1,
+            });
+        }
+        stats.row(row.len());
+        stats.back_pointers(row.len());
+        pointers.push(row_pointers);
+        previous = row;
+        previous_range = range;
+    }
+    let last = previous[left.len() - previous_range.start];
+    let state = pick_best_final(
+        last.last_was_mutation,
+        last.last_was_insert_left,
+        last.last_was_insert_right,
+    );
+    let operations = trace_back(left, right, state, |i, j| {
+        let range = columns(i);
+        // The path touches the edge of the band, unless it's also the edge of the matrix.
+        if (j == range.start && j > 0) || (j + 1 == range.end && j < left.len()) {
+            None
+        } else {
+            Some(pointers[i][j - range.start])
+        }
+    })?;
+    Some(Alignment {
+        operations,
+        score: last.best(),
+        offsets: vec![],
+    })
+}
+
+/// Computes the same alignment as `align` without keeping back pointers. The first pass computes
+/// scores and keeps every √m-th row as a checkpoint. The second pass walks the path backwards and
+/// recomputes the rows it needs from the nearest checkpoint. This takes about twice the time of
+/// `align`, but only O(n·√m) memory for scores.
+pub fn align_low_memory<'a, T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+) -> Alignment<'a, T> {
+    align_low_memory_with_stats(scoring, left, right, &mut ())
+}
+
+/// Same as `align_low_memory`, reporting the work done to `stats`.
+pub fn align_low_memory_with_stats<'a, T, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    stats: &mut C,
+) -> Alignment<'a, T> {
+    if left.is_empty() || right.is_empty() {
+        return align_identical(scoring, left, right);
+    }
+    let block = ((right.len() + 1) as f64).sqrt().ceil() as usize;
+    let mut current = ScoreState::first_row(scoring, left);
+    stats.row(current.len());
+    // `checkpoints[b]` is the row after `b * block` right tokens.
+    let mut checkpoints = vec![current.clone()];
+    let mut next = Vec::with_capacity(left.len() + 1);
+    for (r_index, r) in right.iter().enumerate() {
+        ScoreState::next_row(scoring, left, r, &current, &mut next);
+        stats.row(next.len());
+        std::mem::swap(&mut current, &mut next);
+        if (r_index + 1) % block == 0 {
+            checkpoints.push(current.clone());
+        }
+    }
+    let last = current[left.len()];
+    let mut state = pick_best_final(
+        last.last_was_mutation,
+        last.last_was_insert_left,
+        last.last_was_insert_right,
+    );
+
+    // Rows of the block with the given index, recomputed from its checkpoint.
+    let block_rows = |index: usize, stats: &mut C| {
+        let first = index * block;
+        let mut rows = vec![checkpoints[index].clone()];
+        for r in right
+            .iter()
+            .take((first + block).min(right.len() + 1) - 1)
+            .skip(first)
+        {
+            let mut next = Vec::with_capacity(left.len() + 1);
+            ScoreState::next_row(scoring, left, r, rows.last().unwrap(), &mut next);
+            stats.row(next.len());
+            rows.push(next);
+        }
+        rows
+    };
+    let mut cached_block = None;
+    let mut rows = vec![];
+    let mut operations = vec![];
+    let (mut i, mut j) = (right.len(), left.len());
+    while i > 0 || j > 0 {
+        let (operation, prev_i, prev_j) = match state {
+            LastOperation::Mutation => (
+                AlignmentOperation::Mutation {
+                    left: &left[j - 1],
+                    right: &right[i - 1],
+                },
+                i - 1,
+                j - 1,
+            ),
+            LastOperation::InsertLeft => (
+                AlignmentOperation::InsertLeft { left: &left[j - 1] },
+                i,
+                j - 1,
+            ),
+            LastOperation::InsertRight => (
+                AlignmentOperation::InsertRight {
+                    right: &right[i - 1],
+                },
+                i - 1,
+                j,
+            ),
+        };
+        if cached_block != Some(prev_i / block) {
+            cached_block = Some(prev_i / block);
+            rows = block_rows(prev_i / block, stats);
+        }
+        let prev = rows[prev_i % block][prev_j];
+        let (mutation, insert_left, insert_right) = match &operation {
+            AlignmentOperation::Mutation { left, right } => {
+                prev.mutation_candidates(scoring, *left, *right)
+            }
+            AlignmentOperation::InsertLeft { left } => prev.insert_left_candidates(scoring, *left),
+            AlignmentOperation::InsertRight { right } => {
+                prev.insert_right_candidates(scoring, *right)
+            }
+        };
+        state = pick_best_predecessor(mutation, insert_left, insert_right).1;
+        operations.push(operation);
+        i = prev_i;
+        j = prev_j;
+    }
+    operations.reverse();
     Alignment {
-        operations: Rc::try_unwrap(result_path)
-            .unwrap_or_else(|x| {
-                eprintln!("More than 1 reference!");
-                (*x).clone()
-            })
-            .extract_path(),
+        operations,
+        score: last.best(),
+        offsets: vec![],
+    }
+}
+
+/// Aligns each left token to the right token at the same position, without searching for an
+/// alignment. This is the optimal alignment when both sides consist of the same tokens, so it's a
+/// fast path for identical inputs. Extra tokens on the longer side become inserts, so when one
+/// side is empty this is the only alignment, and the other aligners return it right away.
+pub fn align_identical<'a, T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+) -> Alignment<'a, T> {
+    let common = left.len().min(right.len());
+    let operations: Vec<_> = left
+        .iter()
+        .zip(right)
+        .map(|(left, right)| AlignmentOperation::Mutation { left, right })
+        .chain(
+            left[common..]
+                .iter()
+                .map(|left| AlignmentOperation::InsertLeft { left }),
+        )
+        .chain(
+            right[common..]
+                .iter()
+                .map(|right| AlignmentOperation::InsertRight { right }),
+        )
+        .collect();
+    Alignment {
+        score: operations_cost(scoring, &operations),
+        operations,
+        offsets: vec![],
     }
 }
 
+/// Computes a shortest edit script with Myers' O(N·D) algorithm, which is much faster than
+/// `align` when the inputs differ in few tokens. Tokens are matched only when they are equal (same
+/// text and kind of block token), so `Mutation`s are always equalities, and `scoring` is used only
+/// for the score of the result.
+pub fn align_myers<'a, T: Token, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+) -> Alignment<'a, T> {
+    align_myers_with_stats(scoring, left, right, &mut ())
+}
+
+/// Same as `align_myers`, reporting the work done to `stats`. A row is one edit distance, with
+/// a cell per diagonal.
+pub fn align_myers_with_stats<'a, T: Token, S: AlignmentScoring<T>, C: StatsCollector>(
+    scoring: &S,
+    left: &'a [T],
+    right: &'a [T],
+    stats: &mut C,
+) -> Alignment<'a, T> {
+    if left.is_empty() || right.is_empty() {
+        return align_identical(scoring, left, right);
+    }
+    let (n, m) = (left.len() as isize, right.len() as isize);
+    let max = n + m;
+    // `furthest[k + offset]` is the furthest left index reached on diagonal `k = x - y`.
+    let offset = max + 1;
+    let mut furthest = vec![0isize; 2 * max as usize + 3];
+    // `trace[d][k + d]` is the furthest left index on diagonal `k` with `d` edits.
+    let mut trace: Vec<Vec<isize>> = vec![];
+    'edits: for d in 0..=max {
+        stats.row(d as usize + 1);
+        stats.back_pointers(2 * d as usize + 1);
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && furthest[index - 1] < furthest[index + 1]) {
+                furthest[index + 1]
+            } else {
+                furthest[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && same(&left[x as usize], &right[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            furthest[index] = x;
+            if x >= n && y >= m {
+                trace.push(furthest[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                break 'edits;
+            }
+        }
+        trace.push(furthest[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+    let mut operations = Vec::with_capacity((n + m) as usize);
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let previous = &trace[d as usize - 1];
+        let at = |k: isize| previous[(k + d - 1) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && at(k - 1) < at(k + 1));
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let previous_x = at(previous_k);
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            operations.push(AlignmentOperation::Mutation {
+                left: &left[x as usize],
+                right: &right[y as usize],
+            });
+        }
+        if down {
+            operations.push(AlignmentOperation::InsertRight {
+                right: &right[previous_y as usize],
+            });
+        } else {
+            operations.push(AlignmentOperation::InsertLeft {
+                left: &left[previous_x as usize],
+            });
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        operations.push(AlignmentOperation::Mutation {
+            left: &left[x as usize],
+            right: &right[y as usize],
+        });
+    }
+    operations.reverse();
+    let score = operations_cost(scoring, &operations);
+    Alignment {
+        operations,
+        score,
+        offsets: vec![],
+    }
+}
+
+/// Aligns parts of `left` and `right` between consecutive `splits` independently using `aligner`,
+/// so no token is ever matched across a split. `splits` are pairs of (left, right) indices and have to be
+/// non-decreasing on both sides.
+pub fn align_segments<'a, T, F>(
+    mut aligner: F,
+    left: &'a [T],
+    right: &'a [T],
+    splits: &[(usize, usize)],
+) -> Alignment<'a, T>
+where
+    F: FnMut(&'a [T], &'a [T]) -> Alignment<'a, T>,
+{
+    let mut alignment = Alignment {
+        operations: vec![],
+        score: 0.,
+        offsets: vec![],
+    };
+    for (left_range, right_range) in segments(left.len(), right.len(), splits) {
+        alignment = alignment.concat(aligner(&left[left_range], &right[right_range]), 0, 0);
+    }
+    alignment
+}
+
+/// Ranges of left and right tokens between consecutive `splits`, as aligned by `align_segments`.
+pub fn segments(
+    left_len: usize,
+    right_len: usize,
+    splits: &[(usize, usize)],
+) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut segments = Vec::with_capacity(splits.len() + 1);
+    let (mut left_start, mut right_start) = (0, 0);
+    for &(left_end, right_end) in splits.iter().chain(std::iter::once(&(left_len, right_len))) {
+        let left_end = left_end.clamp(left_start, left_len);
+        let right_end = right_end.clamp(right_start, right_len);
+        segments.push((left_start..left_end, right_start..right_end));
+        left_start = left_end;
+        right_start = right_end;
+    }
+    segments
+}
+
 #[derive(Debug)]
 pub struct Alignment<'a, T> {
     operations: Vec<AlignmentOperation<&'a T>>,
+    score: f64,
+    /// Byte offsets of the tokens of parts appended by `concat`, as (index of the first operation
+    /// of the part, left offset, right offset), in the order of the operations.
+    offsets: Vec<(usize, usize, usize)>,
 }
 
 impl<T> AlignmentOperation<T> {
@@ -237,32 +1294,708 @@ impl<T> AlignmentOperation<T> {
             AlignmentOperation::InsertRight { right } => Some(right),
         }
     }
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> AlignmentOperation<U> {
+        match self {
+            AlignmentOperation::Mutation { left, right } => AlignmentOperation::Mutation {
+                left: f(left),
+                right: f(right),
+            },
+            AlignmentOperation::InsertLeft { left } => {
+                AlignmentOperation::InsertLeft { left: f(left) }
+            }
+            AlignmentOperation::InsertRight { right } => {
+                AlignmentOperation::InsertRight { right: f(right) }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Matching `BlockStart` and `BlockEnd` tokens of one side, as indices into `operations`.
+#[derive(Debug, Clone)]
+pub struct BlockPair {
+    pub start: usize,
+    pub end: usize,
+    pub side: Side,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Show entirely inserted or deleted blocks as a single line.
+    pub collapse_block_changes: bool,
+    /// Show deleted and inserted runs with the same tokens as moved text.
+    pub detect_moves: bool,
+    /// Highlight only the differing characters of mutated words.
+    pub refine_words: bool,
+    /// Expand tabs to spaces in the output.
+    pub expand_tabs: bool,
+    /// Tab stops are this many columns apart when expanding tabs. Must be positive.
+    pub tab_width: usize,
+    /// Separate the change marker from the text with a tab instead of a space.
+    pub initial_tab: bool,
+    pub emphasis: Emphasis,
+    /// Fold unchanged lines further than this many lines from a change.
+    pub context: Option<usize>,
+    /// Wrap lines containing right-to-left text in a bidi isolate, so terminals with bidi support
+    /// keep the change marker in front of the line.
+    pub isolate_bidi: bool,
+    /// Show words which differ only in case underlined instead of as a mutation.
+    pub show_case_changes: bool,
+    /// Show words which differ only in case as unchanged, unless `show_case_changes` is set.
+    pub ignore_case: bool,
+    /// Show tokens which both match this regex as unchanged.
+    pub ignore_matching: Option<Regex>,
+    /// Every token is a whole line including its line break, so each is rendered as a line.
+    pub whole_lines: bool,
+    /// Show runs of blank lines as a single blank line.
+    pub squeeze_blank: bool,
+    /// Show only the added or only the removed part of changed lines, and no unchanged lines.
+    pub only: Option<ChangeKind>,
+    /// Show both documents in two columns next to each other, fitting into this many columns.
+    pub side_by_side: Option<usize>,
+    pub markers: Markers,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            collapse_block_changes: false,
+            detect_moves: false,
+            refine_words: false,
+            expand_tabs: false,
+            tab_width: TAB_WIDTH,
+            initial_tab: false,
+            emphasis: Emphasis::default(),
+            context: None,
+            isolate_bidi: false,
+            show_case_changes: false,
+            ignore_case: false,
+            ignore_matching: None,
+            whole_lines: false,
+            squeeze_blank: false,
+            only: None,
+            side_by_side: None,
+            markers: Markers::default(),
+        }
+    }
+}
+
+/// Prefixes of unchanged, deleted and added lines in the pretty output.
+#[derive(Debug, Clone)]
+pub struct Markers {
+    pub same: String,
+    pub del: String,
+    pub add: String,
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        Markers {
+            same: " ".to_string(),
+            del: "-".to_string(),
+            add: "+".to_string(),
+        }
+    }
+}
+
+/// Side of changes, for showing only one of them.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ChangeKind {
+    /// Text in the right document only.
+    Added,
+    /// Text in the left document only.
+    Removed,
+}
+
+/// Which kind of changes is highlighted in the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Emphasis {
+    /// Highlight changed text.
+    #[default]
+    Text,
+    /// Highlight changed blocks (indentation), and dim changed text.
+    Structure,
+}
+
+/// Default distance of tab stops in the output.
+pub const TAB_WIDTH: usize = 8;
+
+/// Note shown after the last line of a document without a line break at the end, as in `diff`.
+pub const NO_NEWLINE: &str = "\\ No newline at end of file";
+
+/// Deleted and inserted runs with fewer tokens (not counting whitespace) are never shown as moved,
+/// so short common runs like `)` or `return x` aren't.
+const MIN_MOVED_TOKENS: usize = 8;
+
+/// Width of `text` in terminal columns. Wide characters take two columns, combining marks none,
+/// and control characters one.
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| c.width().unwrap_or(1)).sum()
+}
+
+/// Expands tabs to spaces with tab stops every `tab_width` columns, counting columns by display
+/// width and not counting ANSI escape sequences.
+fn expand_tabs(text: &str, tab_width: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut column = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        if in_escape {
+            in_escape = !c.is_ascii_alphabetic();
+            out.push(c);
+        } else if c == '\x1b' {
+            in_escape = true;
+            out.push(c);
+        } else if c == '\t' {
+            let spaces = tab_width - column % tab_width;
+            out.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            out.push(c);
+            column += c.width().unwrap_or(0);
+        }
+    }
+    out
+}
+
+/// Whether the character is a strong right-to-left character (Hebrew, Arabic, Syriac, Thaana, NKo
+/// and related presentation forms).
+fn is_rtl(c: char) -> bool {
+    matches!(c, '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+/// Cuts `text` to at most `width` display columns, not counting ANSI escape sequences. Returns the
+/// cut text and its width. Colors are reset if the text was cut.
+fn truncate_columns(text: &str, width: usize) -> (String, usize) {
+    let mut out = String::with_capacity(text.len());
+    let mut column = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        if in_escape {
+            in_escape = !c.is_ascii_alphabetic();
+        } else if c == '\x1b' {
+            in_escape = true;
+        } else {
+            let c_width = c.width().unwrap_or(0);
+            if column + c_width > width {
+                if text.contains('\x1b') {
+                    out.push_str("\x1b[0m");
+                }
+                break;
+            }
+            column += c_width;
+        }
+        out.push(c);
+    }
+    (out, column)
+}
+
+/// First strong isolate, the direction of the text is determined by its first strong character.
+const FIRST_STRONG_ISOLATE: char = '\u{2068}';
+const POP_DIRECTIONAL_ISOLATE: char = '\u{2069}';
+
+/// Builder methods, e.g. `RenderOptions::default().with_context(Some(3))`. See the fields for
+/// what each option does.
+impl RenderOptions {
+    pub fn with_collapse_block_changes(mut self, collapse_block_changes: bool) -> Self {
+        self.collapse_block_changes = collapse_block_changes;
+        self
+    }
+
+    pub fn with_detect_moves(mut self, detect_moves: bool) -> Self {
+        self.detect_moves = detect_moves;
+        self
+    }
+
+    pub fn with_refine_words(mut self, refine_words: bool) -> Self {
+        self.refine_words = refine_words;
+        self
+    }
+
+    pub fn with_expand_tabs(mut self, expand_tabs: bool) -> Self {
+        self.expand_tabs = expand_tabs;
+        self
+    }
+
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width;
+        self
+    }
+
+    pub fn with_initial_tab(mut self, initial_tab: bool) -> Self {
+        self.initial_tab = initial_tab;
+        self
+    }
+
+    pub fn with_emphasis(mut self, emphasis: Emphasis) -> Self {
+        self.emphasis = emphasis;
+        self
+    }
+
+    pub fn with_context(mut self, context: Option<usize>) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_isolate_bidi(mut self, isolate_bidi: bool) -> Self {
+        self.isolate_bidi = isolate_bidi;
+        self
+    }
+
+    pub fn with_show_case_changes(mut self, show_case_changes: bool) -> Self {
+        self.show_case_changes = show_case_changes;
+        self
+    }
+
+    pub fn with_ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    pub fn with_ignore_matching(mut self, ignore_matching: Option<Regex>) -> Self {
+        self.ignore_matching = ignore_matching;
+        self
+    }
+
+    pub fn with_whole_lines(mut self, whole_lines: bool) -> Self {
+        self.whole_lines = whole_lines;
+        self
+    }
+
+    pub fn with_squeeze_blank(mut self, squeeze_blank: bool) -> Self {
+        self.squeeze_blank = squeeze_blank;
+        self
+    }
+
+    pub fn with_only(mut self, only: Option<ChangeKind>) -> Self {
+        self.only = only;
+        self
+    }
+
+    pub fn with_side_by_side(mut self, side_by_side: Option<usize>) -> Self {
+        self.side_by_side = side_by_side;
+        self
+    }
+
+    pub fn with_markers(mut self, markers: Markers) -> Self {
+        self.markers = markers;
+        self
+    }
+}
+
+impl RenderOptions {
+    /// Whether tokens with different texts are shown as unchanged, i.e. they differ only in case
+    /// with `ignore_case` (and without `show_case_changes`), or both match `ignore_matching`.
+    pub fn shows_as_same(&self, left: &str, right: &str) -> bool {
+        let same_lowercase = || left.to_lowercase() == right.to_lowercase();
+        (self.ignore_case && !self.show_case_changes && same_lowercase())
+            || self
+                .ignore_matching
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(left) && regex.is_match(right))
+    }
+
+    /// Formats one output line with the given change marker.
+    pub fn line(&self, marker: &str, text: &str) -> String {
+        let separator = if self.initial_tab { '\t' } else { ' ' };
+        format!("{}{}{}", marker, separator, self.text(text))
+    }
+
+    /// Formats the text of one output line, without any marker.
+    pub fn text(&self, text: &str) -> String {
+        let text = if self.expand_tabs {
+            expand_tabs(text, self.tab_width)
+        } else {
+            text.to_string()
+        };
+        if self.isolate_bidi && text.chars().any(is_rtl) {
+            format!(
+                "{}{}{}",
+                FIRST_STRONG_ISOLATE, text, POP_DIRECTIONAL_ISOLATE
+            )
+        } else {
+            text
+        }
+    }
+}
+
+/// Single grapheme of a word, used for aligning characters of mutated words. Graphemes are used so
+/// combining marks are never separated from their base character.
+#[derive(Debug)]
+struct CharToken<'a> {
+    text: &'a str,
+    start: usize,
+}
+
+impl<'a> CharToken<'a> {
+    pub fn split(text: &'a str) -> Vec<Self> {
+        text.grapheme_indices(true)
+            .map(|(start, text)| CharToken { text, start })
+            .collect()
+    }
+}
+
+impl<'a> Token for CharToken<'a> {
+    fn text(&self) -> &str {
+        self.text
+    }
+
+    fn start(&self) -> usize {
+        self.start
+    }
+
+    fn is_whitespace(&self) -> bool {
+        self.text.chars().all(|x| x.is_whitespace())
+    }
+
+    fn is_block_start(&self) -> bool {
+        false
+    }
+
+    fn is_block_end(&self) -> bool {
+        false
+    }
+
+    fn is_word(&self) -> bool {
+        self.text.chars().all(|x| x.is_alphanumeric() || x == '_')
+    }
+}
+
+struct CharScoring;
+
+impl<'a> AlignmentScoring<CharToken<'a>> for CharScoring {
+    fn insert_score(&self, _inserted: &CharToken<'a>, previous_is_same: bool) -> f64 {
+        if previous_is_same {
+            0.3
+        } else {
+            0.7
+        }
+    }
+
+    fn mutation_score(
+        &self,
+        left: &CharToken<'a>,
+        right: &CharToken<'a>,
+        _previous_is_same: bool,
+    ) -> f64 {
+        if left.text == right.text {
+            0.
+        } else {
+            1.
+        }
+    }
+}
+
+/// One line of the rendered diff, as returned by `Alignment::lines`. Texts contain the ANSI color
+/// escapes of the output, unless colors are disabled.
+#[derive(Debug, Clone)]
+pub enum OutputLine {
+    /// Line which is the same in both documents.
+    Same { line: String },
+    /// Changed line. `left` has only the deleted text, at the columns where it is in `right`,
+    /// `right` is the line of the right document with the deleted text struck through.
+    Change {
+        left: Option<String>,
+        right: Option<String>,
+        /// The line as it is in the left and in the right document, `None` if it has no text on
+        /// that side. Used by the side-by-side output.
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// Entirely deleted or inserted block of `lines` lines, with `collapse_block_changes`.
+    CollapsedBlock { side: Side, lines: usize },
+    /// Unchanged lines of the right document which are not shown.
+    Folded {
+        /// First folded line (1-based).
+        first: usize,
+        lines: usize,
+    },
+    /// The document of `side` doesn't end with a line break, while the other one does. Follows
+    /// the last line.
+    NoNewline { side: Side },
+}
+
+impl OutputLine {
+    /// Whether the line shows a change.
+    pub fn is_change(&self) -> bool {
+        matches!(
+            self,
+            OutputLine::Change { .. }
+                | OutputLine::CollapsedBlock { .. }
+                | OutputLine::NoNewline { .. }
+        )
+    }
+
+    /// The line as it is in the left document, if it has text there.
+    pub fn left_line(&self) -> Option<&str> {
+        match self {
+            OutputLine::Same { line } => Some(line),
+            OutputLine::Change { old, .. } => old.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The line as it is in the right document, if it has text there.
+    pub fn right_line(&self) -> Option<&str> {
+        match self {
+            OutputLine::Same { line } => Some(line),
+            OutputLine::Change { new, .. } => new.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Number of lines of the right document this output line stands for.
+    pub fn right_lines(&self) -> usize {
+        match self {
+            OutputLine::Same { .. } | OutputLine::Change { .. } => 1,
+            OutputLine::CollapsedBlock {
+                side: Side::Left, ..
+            } => 0,
+            OutputLine::CollapsedBlock {
+                side: Side::Right,
+                lines,
+            } => *lines,
+            OutputLine::Folded { lines, .. } => *lines,
+            OutputLine::NoNewline { .. } => 0,
+        }
+    }
+}
+
+/// Pretty output of one output line, which is one or two `\n` terminated lines.
+fn render_line(options: &RenderOptions, line: OutputLine) -> String {
+    if let Some(width) = options.side_by_side {
+        return side_by_side_line(line, width, options.tab_width);
+    }
+    let mut out = String::new();
+    let mut push_line = |line: &str| {
+        out.push_str(line);
+        out.push('\n');
+    };
+    match line {
+        OutputLine::Same { line } => {
+            push_line(&options.line(&options.markers.same, &line));
+        }
+        OutputLine::Change { left, right, .. } => {
+            if let Some(left) = left {
+                push_line(&options.line(&options.markers.del, &left));
+            }
+            if let Some(right) = right {
+                push_line(&options.line(&options.markers.add, &right));
+            }
+        }
+        OutputLine::CollapsedBlock { side, lines } => {
+            let text = format!("block ({} lines)", lines);
+            match side {
+                Side::Left => {
+                    push_line(&options.line(&options.markers.del, &text.red().to_string()))
+                }
+                Side::Right => {
+                    push_line(&options.line(&options.markers.add, &text.green().to_string()))
+                }
+            }
+        }
+        OutputLine::Folded { first, lines } => {
+            let text = format!(
+                "@@ {} unchanged line{} (L{}-L{}) @@",
+                lines,
+                if lines == 1 { "" } else { "s" },
+                first,
+                first + lines - 1
+            );
+            push_line(&text.cyan().to_string());
+        }
+        OutputLine::NoNewline { side } => {
+            let marker = match side {
+                Side::Left => &options.markers.del,
+                Side::Right => &options.markers.add,
+            };
+            push_line(&options.line(marker, NO_NEWLINE));
+        }
+    }
+    out
+}
+
+/// The line with only the `only` side of a change, or `None` when nothing of it is left.
+fn only_changes(line: OutputLine, only: Option<ChangeKind>) -> Option<OutputLine> {
+    let Some(only) = only else {
+        return Some(line);
+    };
+    match line {
+        OutputLine::Same { .. } | OutputLine::Folded { .. } => None,
+        OutputLine::Change {
+            left,
+            right,
+            old,
+            new,
+        } => match only {
+            ChangeKind::Added => right.map(|right| OutputLine::Change {
+                left: None,
+                right: Some(right),
+                old: None,
+                new,
+            }),
+            ChangeKind::Removed => left.map(|left| OutputLine::Change {
+                left: Some(left),
+                right: None,
+                old,
+                new: None,
+            }),
+        },
+        OutputLine::CollapsedBlock { side, lines } => {
+            let shown = match only {
+                ChangeKind::Added => Side::Right,
+                ChangeKind::Removed => Side::Left,
+            };
+            (side == shown).then_some(OutputLine::CollapsedBlock { side, lines })
+        }
+        OutputLine::NoNewline { side } => {
+            let shown = match only {
+                ChangeKind::Added => Side::Right,
+                ChangeKind::Removed => Side::Left,
+            };
+            (side == shown).then_some(OutputLine::NoNewline { side })
+        }
+    }
+}
+
+/// Row of the side by side output for one output line, with a gutter marking changed lines with
+/// `|`, lines only in the left document with `<` and lines only in the right document with `>`.
+/// Columns are cut to fit into `width` columns, with tabs expanded to stops every `tab_width`
+/// columns.
+fn side_by_side_line(line: OutputLine, width: usize, tab_width: usize) -> String {
+    let column = width.saturating_sub(3) / 2;
+    let row = |left: &str, gutter: char, right: &str| {
+        let (left, left_width) = truncate_columns(&expand_tabs(left, tab_width), column);
+        let (right, _) = truncate_columns(&expand_tabs(right, tab_width), column);
+        let row = format!(
+            "{}{} {} {}",
+            left,
+            " ".repeat(column - left_width),
+            gutter,
+            right
+        );
+        format!("{}\n", row.trim_end())
+    };
+    match line {
+        OutputLine::Same { line } => row(&line, ' ', &line),
+        OutputLine::Change { old, new, .. } => {
+            let gutter = match (&old, &new) {
+                (Some(_), None) => '<',
+                (None, Some(_)) => '>',
+                _ => '|',
+            };
+            row(
+                old.as_deref().unwrap_or(""),
+                gutter,
+                new.as_deref().unwrap_or(""),
+            )
+        }
+        OutputLine::CollapsedBlock { side, lines } => {
+            let text = format!("block ({} lines)", lines);
+            match side {
+                Side::Left => row(&text.red().to_string(), '<', ""),
+                Side::Right => row("", '>', &text.green().to_string()),
+            }
+        }
+        OutputLine::Folded { first, lines } => {
+            let text = format!(
+                "@@ {} unchanged line{} (L{}-L{}) @@",
+                lines,
+                if lines == 1 { "" } else { "s" },
+                first,
+                first + lines - 1
+            );
+            format!("{}\n", text.cyan())
+        }
+        OutputLine::NoNewline { side: Side::Left } => row(NO_NEWLINE, '<', ""),
+        OutputLine::NoNewline { side: Side::Right } => row("", '>', NO_NEWLINE),
+    }
+}
+
+/// Replaces runs of unchanged lines further than `context` lines from any change with a single
+/// `Folded` line.
+fn fold(lines: Vec<OutputLine>, context: usize) -> Vec<OutputLine> {
+    let changed: Vec<bool> = lines
+        .iter()
+        .map(|line| !matches!(line, OutputLine::Same { .. }))
+        .collect();
+    let mut distance = vec![usize::MAX; lines.len()];
+    let mut last_change = None;
+    for index in 0..lines.len() {
+        if changed[index] {
+            last_change = Some(index);
+        }
+        if let Some(last) = last_change {
+            distance[index] = index - last;
+        }
+    }
+    last_change = None;
+    for index in (0..lines.len()).rev() {
+        if changed[index] {
+            last_change = Some(index);
+        }
+        if let Some(last) = last_change {
+            distance[index] = distance[index].min(last - index);
+        }
+    }
+    let mut out = Vec::with_capacity(lines.len());
+    let mut right_line = 1;
+    let count = lines.len();
+    for (index, (line, distance)) in lines.into_iter().zip(distance).enumerate() {
+        let right_lines = match &line {
+            // Empty line after the final newline is not a line of the document.
+            OutputLine::Same { line } if index + 1 == count && line.is_empty() => 0,
+            line => line.right_lines(),
+        };
+        if distance <= context {
+            out.push(line);
+        } else if right_lines == 0 {
+            continue;
+        } else if let Some(OutputLine::Folded { lines, .. }) = out.last_mut() {
+            *lines += right_lines;
+        } else {
+            out.push(OutputLine::Folded {
+                first: right_line,
+                lines: right_lines,
+            });
+        }
+        right_line += right_lines;
+    }
+    out
 }
 
-enum OutputLine {
-    Same {
-        line: String,
-    },
-    Change {
-        left: Option<String>,
-        right: Option<String>,
-    },
-}
-
-struct DiffLineOutput {
+/// Builds output lines from operations, passing each completed line to `out`.
+struct DiffLineOutput<'o> {
     left: String,
     right: String,
     equal: bool,
-    out: Vec<OutputLine>,
+    /// The line as it is in the left and in the right document.
+    old: String,
+    new: String,
+    /// Whether `old` and `new` contain anything besides whitespace.
+    old_text: bool,
+    new_text: bool,
+    emphasis: Emphasis,
+    out: &'o mut dyn FnMut(OutputLine),
 }
 
-impl DiffLineOutput {
-    pub fn new() -> Self {
+impl<'o> DiffLineOutput<'o> {
+    pub fn new(emphasis: Emphasis, out: &'o mut dyn FnMut(OutputLine)) -> Self {
         let mut ret = Self {
             left: String::new(),
             right: String::new(),
             equal: true,
-            out: Vec::new(),
+            old: String::new(),
+            new: String::new(),
+            old_text: false,
+            new_text: false,
+            emphasis,
+            out,
         };
         ret.clear();
         ret
@@ -272,15 +2005,29 @@ impl DiffLineOutput {
         self.left.clear();
         self.right.clear();
         self.equal = true;
+        self.old.clear();
+        self.new.clear();
+        self.old_text = false;
+        self.new_text = false;
+    }
+
+    fn push_old(&mut self, text: &str, styled: impl std::fmt::Display) {
+        self.old_text |= text.chars().any(|x| !x.is_whitespace());
+        self.old.push_str(&styled.to_string());
+    }
+
+    fn push_new(&mut self, text: &str, styled: impl std::fmt::Display) {
+        self.new_text |= text.chars().any(|x| !x.is_whitespace());
+        self.new.push_str(&styled.to_string());
     }
 
     pub fn flush(&mut self) {
         if self.equal {
-            self.out.push(OutputLine::Same {
+            (self.out)(OutputLine::Same {
                 line: self.right.clone(),
             })
         } else {
-            self.out.push(OutputLine::Change {
+            (self.out)(OutputLine::Change {
                 left: if self.left.chars().any(|x| !x.is_whitespace()) {
                     Some(self.left.clone())
                 } else {
@@ -291,6 +2038,8 @@ impl DiffLineOutput {
                 } else {
                     None
                 },
+                old: self.old_text.then(|| self.old.clone()),
+                new: self.new_text.then(|| self.new.clone()),
             });
         }
         self.clear();
@@ -299,74 +2048,686 @@ impl DiffLineOutput {
     pub fn add_same(&mut self, line: &str) {
         self.left.extend(line.chars().map(|_| ' '));
         self.right.push_str(line);
+        self.push_old(line, line);
+        self.push_new(line, line);
     }
 
-    pub fn add_mutation(&mut self, left: &str, right: &str) {
+    fn removed(&self, text: &str) -> ColoredString {
+        match self.emphasis {
+            Emphasis::Text => text.red(),
+            Emphasis::Structure => text.dimmed(),
+        }
+    }
+
+    fn added(&self, text: &str) -> ColoredString {
+        match self.emphasis {
+            Emphasis::Text => text.green(),
+            Emphasis::Structure => text.dimmed(),
+        }
+    }
+
+    /// Marks a changed block boundary. Only visible when structure is emphasized.
+    pub fn block_change(&mut self, side: Side, start: bool) {
+        if self.emphasis != Emphasis::Structure {
+            return;
+        }
         self.equal = false;
-        self.left.extend(format!("{}", left.red()).chars());
-        self.right.extend(format!("{}", right.green()).chars());
-        if left.len() < right.len() {
-            for _ in 0..(right.len() - left.len()) {
-                self.left.push(' ');
-            }
+        let marker = if start { "⇥" } else { "⇤" };
+        let marker = match side {
+            Side::Left => marker.red().bold().strikethrough(),
+            Side::Right => marker.green().bold(),
+        };
+        self.left.push(' ');
+        self.right.extend(format!("{}", marker).chars());
+        match side {
+            Side::Left => self.push_old("", &marker),
+            Side::Right => self.push_new("", &marker),
+        }
+    }
+
+    /// Pads the side with the narrower text, so both continue in the same column.
+    fn pad(&mut self, left: &str, right: &str) {
+        let (left, right) = (display_width(left), display_width(right));
+        if left < right {
+            self.left.extend(std::iter::repeat_n(' ', right - left));
         } else {
-            for _ in 0..(left.len() - right.len()) {
-                self.right.push(' ');
+            self.right.extend(std::iter::repeat_n(' ', left - right));
+        }
+    }
+
+    pub fn add_mutation(&mut self, left: &str, right: &str) {
+        self.equal = false;
+        self.left.extend(format!("{}", self.removed(left)).chars());
+        self.right.extend(format!("{}", self.added(right)).chars());
+        self.push_old(left, self.removed(left));
+        self.push_new(right, self.added(right));
+        self.pad(left, right);
+    }
+
+    /// Like `add_mutation`, for texts which differ only in case.
+    pub fn add_case_change(&mut self, left: &str, right: &str) {
+        self.equal = false;
+        self.left.extend(format!("{}", left.underline()).chars());
+        self.right.extend(format!("{}", right.underline()).chars());
+        self.push_old(left, left.underline());
+        self.push_new(right, right.underline());
+        self.pad(left, right);
+    }
+
+    /// Like `add_mutation`, but only characters that differ are highlighted.
+    pub fn add_refined_mutation(&mut self, left: &str, right: &str) {
+        self.equal = false;
+        let left_chars = CharToken::split(left);
+        let right_chars = CharToken::split(right);
+        for operation in align(&CharScoring, &left_chars, &right_chars).operations {
+            match operation {
+                AlignmentOperation::Mutation { left, right } if left.text == right.text => {
+                    self.left.push_str(left.text);
+                    self.right.push_str(right.text);
+                    self.push_old(left.text, left.text);
+                    self.push_new(right.text, right.text);
+                }
+                AlignmentOperation::Mutation { left, right } => {
+                    self.left.extend(format!("{}", left.text.red()).chars());
+                    self.right.extend(format!("{}", right.text.green()).chars());
+                    self.push_old(left.text, left.text.red());
+                    self.push_new(right.text, right.text.green());
+                }
+                AlignmentOperation::InsertLeft { left } => {
+                    self.left.extend(format!("{}", left.text.red()).chars());
+                    self.push_old(left.text, left.text.red());
+                }
+                AlignmentOperation::InsertRight { right } => {
+                    self.right.extend(format!("{}", right.text.green()).chars());
+                    self.push_new(right.text, right.text.green());
+                }
             }
         }
+        self.pad(left, right);
     }
 
     pub fn insert_left(&mut self, text: &str) {
         if text.chars().any(|x| !x.is_whitespace()) {
             self.equal = false;
         }
-        self.left.extend(text.chars().map(|_| ' '));
+        self.left
+            .extend(std::iter::repeat_n(' ', display_width(text)));
         self.right
-            .extend(format!("{}", text.red().strikethrough()).chars());
+            .extend(format!("{}", self.removed(text).strikethrough()).chars());
+        self.push_old(text, self.removed(text));
     }
 
     pub fn insert_right(&mut self, text: &str) {
         if text.chars().any(|x| !x.is_whitespace()) {
             self.equal = false;
         }
-        self.left.extend(text.chars().map(|_| ' '));
-        self.right.extend(format!("{}", text.green()).chars());
+        self.left
+            .extend(std::iter::repeat_n(' ', display_width(text)));
+        self.right.extend(format!("{}", self.added(text)).chars());
+        self.push_new(text, self.added(text));
+    }
+
+    fn moved(&self, text: &str) -> ColoredString {
+        match self.emphasis {
+            Emphasis::Text => text.cyan(),
+            Emphasis::Structure => text.dimmed(),
+        }
+    }
+
+    /// Like `insert_left`, for text which was moved elsewhere.
+    pub fn move_left(&mut self, text: &str) {
+        self.equal = false;
+        self.left
+            .extend(std::iter::repeat_n(' ', display_width(text)));
+        self.right
+            .extend(format!("{}", self.moved(text).strikethrough()).chars());
+        self.push_old(text, self.moved(text));
+    }
+
+    /// Like `insert_right`, for text which was moved from elsewhere.
+    pub fn move_right(&mut self, text: &str) {
+        self.equal = false;
+        self.left
+            .extend(std::iter::repeat_n(' ', display_width(text)));
+        self.right.extend(format!("{}", self.moved(text)).chars());
+        self.push_new(text, self.moved(text));
     }
 
     pub fn insert_right_space(&mut self, text: &str) {
         self.left.push_str(text);
         self.right.push_str(text);
+        self.push_old(text, text);
+        self.push_new(text, text);
+    }
+
+    /// Adds a deleted or inserted line on its own, shown even when it is blank.
+    pub fn whole_line(&mut self, side: Side, text: &str) {
+        let (left, right) = match side {
+            Side::Left => (Some(self.removed(text).to_string()), None),
+            Side::Right => (None, Some(self.added(text).to_string())),
+        };
+        (self.out)(OutputLine::Change {
+            old: left.clone(),
+            new: right.clone(),
+            left,
+            right,
+        });
+    }
+
+    pub fn collapsed_block(&mut self, side: Side, lines: usize) {
+        if self.right.chars().any(|x| !x.is_whitespace()) {
+            self.flush();
+        } else if side == Side::Right {
+            // Indentation of the first line of the block, which is not shown.
+            self.clear();
+        }
+        (self.out)(OutputLine::CollapsedBlock { side, lines });
+    }
+}
+
+impl<'a, T> Alignment<'a, T> {
+    pub fn operations(&self) -> &[AlignmentOperation<&'a T>] {
+        &self.operations
+    }
+
+    pub(crate) fn operations_mut(&mut self) -> &mut Vec<AlignmentOperation<&'a T>> {
+        &mut self.operations
+    }
+
+    /// Appends the operations of `other`, which aligns the parts following the parts aligned by
+    /// `self`. When the parts were tokenized on their own, `left_offset` and `right_offset` are the
+    /// byte offsets of their starts in the whole texts, and are added to the positions of the
+    /// tokens of `other` in `spans` and `offsets`. Parts which are slices of the same token
+    /// sequences already have positions in the whole texts, so their offsets are 0. Cleanups and
+    /// `interleave_tokens` compare positions of tokens without the offsets, so they are run on the
+    /// parts before concatenating them.
+    pub fn concat(
+        mut self,
+        other: Alignment<'a, T>,
+        left_offset: usize,
+        right_offset: usize,
+    ) -> Self {
+        let start = self.operations.len();
+        if self.offsets(start) != (left_offset, right_offset) {
+            self.offsets.push((start, left_offset, right_offset));
+        }
+        self.offsets.extend(
+            other.offsets.into_iter().map(|(index, left, right)| {
+                (start + index, left_offset + left, right_offset + right)
+            }),
+        );
+        self.operations.extend(other.operations);
+        self.score += other.score;
+        self
+    }
+
+    /// Byte offsets in the left and the right text to add to the positions of the tokens of the
+    /// operation at `index`, which are only non-zero for parts appended by `concat` with offsets.
+    pub fn offsets(&self, index: usize) -> (usize, usize) {
+        match self
+            .offsets
+            .partition_point(|&(start, _, _)| start <= index)
+        {
+            0 => (0, 0),
+            part => (self.offsets[part - 1].1, self.offsets[part - 1].2),
+        }
+    }
+
+    /// Total cost of the alignment as computed by the aligner, 0 for identical inputs. Lower is
+    /// better, so it can be used to rank candidates by similarity. Alignments of segments add up
+    /// their scores, and cleanups don't change it.
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+}
+
+/// Cost of a sequence of operations under `scoring`. The sequence is scored as if it was at the
+/// start of an alignment.
+pub fn operations_cost<T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    operations: &[AlignmentOperation<&T>],
+) -> f64 {
+    operation_costs(scoring, operations)
+        .into_iter()
+        .fold(0., |cost, x| cost + x)
+}
+
+/// Cost each operation adds to the sequence under `scoring`, which depends on the operations before
+/// it. They sum up to `operations_cost`.
+pub fn operation_costs<T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    operations: &[AlignmentOperation<&T>],
+) -> Vec<f64> {
+    let mut previous = LastOperation::Mutation;
+    // Length of the run of inserts ending with the previous operation.
+    let mut run = 0;
+    // Whether the previous operation is a match, i.e. a mutation costing nothing.
+    let mut matched = false;
+    let mut costs = Vec::with_capacity(operations.len());
+    for operation in operations {
+        let (score, last) = match operation {
+            AlignmentOperation::Mutation { left, right } => {
+                let score = scoring.mutation_score(left, right, matched);
+                matched = scoring.mutation_score(left, right, false) <= 0.;
+                (score, LastOperation::Mutation)
+            }
+            AlignmentOperation::InsertLeft { left } => {
+                run = if previous == LastOperation::InsertLeft {
+                    run + 1
+                } else {
+                    1
+                };
+                (
+                    scoring.insert_run_score(left, run),
+                    LastOperation::InsertLeft,
+                )
+            }
+            AlignmentOperation::InsertRight { right } => {
+                run = if previous == LastOperation::InsertRight {
+                    run + 1
+                } else {
+                    1
+                };
+                (
+                    scoring.insert_run_score(right, run),
+                    LastOperation::InsertRight,
+                )
+            }
+        };
+        if last != LastOperation::Mutation {
+            matched = false;
+        }
+        costs.push(score);
+        previous = last;
     }
+    costs
+}
+
+/// Byte ranges of the left and the right token of an operation, see `Alignment::spans`.
+pub type OperationSpans = (Option<Range<usize>>, Option<Range<usize>>);
+
+/// Numbers of changed tokens, not counting whitespace, ignored and block tokens.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DiffStats {
+    /// Tokens only in the right document.
+    pub inserted: usize,
+    /// Tokens only in the left document.
+    pub deleted: usize,
+    /// Tokens aligned with a token of different text.
+    pub mutated: usize,
+}
 
-    pub fn output(self) -> Vec<OutputLine> {
-        self.out
+impl std::fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "+{} -{} ~{}", self.inserted, self.deleted, self.mutated)
     }
 }
 
 impl<'a, T: Token> Alignment<'a, T> {
-    fn output_lines(&self) -> Vec<OutputLine> {
-        let mut output = DiffLineOutput::new();
+    /// Counts inserted, deleted and mutated tokens. A mutation of a real token with a block or
+    /// whitespace token counts as an insertion or a deletion of the real one.
+    pub fn stats(&self) -> DiffStats {
+        let counted = |token: &T| {
+            !token.is_whitespace()
+                && !token.is_ignored()
+                && !token.is_block_start()
+                && !token.is_block_end()
+        };
+        let mut stats = DiffStats::default();
+        for operation in &self.operations {
+            match operation {
+                AlignmentOperation::Mutation { left, right } => {
+                    match (counted(left), counted(right)) {
+                        (true, true) if left.text() != right.text() => stats.mutated += 1,
+                        (true, false) => stats.deleted += 1,
+                        (false, true) => stats.inserted += 1,
+                        _ => {}
+                    }
+                }
+                AlignmentOperation::InsertLeft { left } if counted(left) => stats.deleted += 1,
+                AlignmentOperation::InsertRight { right } if counted(right) => stats.inserted += 1,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Byte ranges of the left and right token of each operation in their source texts, in the
+    /// order of `operations`, e.g. for highlighting changes in the original buffers. Block tokens
+    /// have empty ranges. Offsets of parts appended by `concat` are added.
+    pub fn spans(&self) -> Vec<OperationSpans> {
+        let span = |token: &&T, offset: usize| token.start() + offset..token.end() + offset;
+        self.operations
+            .iter()
+            .enumerate()
+            .map(|(index, operation)| {
+                let (left, right) = self.offsets(index);
+                (
+                    operation.left().map(|x| span(x, left)),
+                    operation.right().map(|x| span(x, right)),
+                )
+            })
+            .collect()
+    }
+
+    /// The side whose document doesn't end with a line break while the other one does. Empty
+    /// documents count as ending with one. Whitespace tokens need to be interleaved, as the line
+    /// breaks are in them.
+    pub fn missing_newline(&self) -> Option<Side> {
+        fn ends_with_newline<'t, T: Token + 't>(
+            mut tokens: impl Iterator<Item = &'t &'t T>,
+        ) -> bool {
+            tokens
+                .find(|token| !token.text().is_empty())
+                .is_none_or(|token| token.text().ends_with('\n'))
+        }
+        let operations = self.operations.iter().rev();
+        match (
+            ends_with_newline(operations.clone().filter_map(|x| x.left())),
+            ends_with_newline(operations.filter_map(|x| x.right())),
+        ) {
+            (false, true) => Some(Side::Left),
+            (true, false) => Some(Side::Right),
+            _ => None,
+        }
+    }
+
+    /// Whether any token was inserted, deleted or changed to a different text.
+    pub fn has_changes(&self) -> bool {
+        self.has_changes_by(|left, right| left == right)
+    }
+
+    /// Same as `has_changes`, but changes only in case don't count.
+    pub fn has_changes_ignoring_case(&self) -> bool {
+        self.has_changes_by(|left, right| left.to_lowercase() == right.to_lowercase())
+    }
+
+    /// Same as `has_changes`, with `equal` deciding whether texts of aligned tokens are the same.
+    pub fn has_changes_by(&self, equal: impl Fn(&str, &str) -> bool) -> bool {
+        self.operations.iter().any(|operation| match operation {
+            AlignmentOperation::Mutation { left, right } => !equal(left.text(), right.text()),
+            AlignmentOperation::InsertLeft { .. } | AlignmentOperation::InsertRight { .. } => true,
+        })
+    }
+
+    /// Pairs block tokens of each side using a stack, so unbalanced tokens stay unpaired.
+    pub fn block_pairs(&self) -> Vec<BlockPair> {
+        let mut pairs = vec![];
+        for side in [Side::Left, Side::Right] {
+            let mut stack = vec![];
+            for (index, operation) in self.operations.iter().enumerate() {
+                let token = match side {
+                    Side::Left => operation.left(),
+                    Side::Right => operation.right(),
+                };
+                match token {
+                    Some(token) if token.is_block_start() => stack.push(index),
+                    Some(token) if token.is_block_end() => {
+                        if let Some(start) = stack.pop() {
+                            pairs.push(BlockPair {
+                                start,
+                                end: index,
+                                side,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        pairs.sort_by_key(|pair| pair.start);
+        pairs
+    }
+
+    /// Outermost blocks that were entirely inserted or deleted, keyed by the index of their
+    /// `BlockStart` operation.
+    fn collapsible_blocks(&self) -> HashMap<usize, (BlockPair, usize)> {
+        let mut blocks = HashMap::new();
+        let mut covered_until = None;
+        for pair in self.block_pairs() {
+            if covered_until.map(|end| pair.start <= end).unwrap_or(false) {
+                continue;
+            }
+            let operations = &self.operations[pair.start..=pair.end];
+            let pure = operations
+                .iter()
+                .all(|operation| match (operation, pair.side) {
+                    (AlignmentOperation::InsertLeft { .. }, Side::Left) => true,
+                    (AlignmentOperation::InsertRight { .. }, Side::Right) => true,
+                    (AlignmentOperation::InsertLeft { left }, Side::Right) => {
+                        left.is_whitespace() || left.is_ignored()
+                    }
+                    _ => false,
+                });
+            if !pure {
+                continue;
+            }
+            let lines = operations
+                .iter()
+                .filter_map(|operation| match pair.side {
+                    Side::Left => operation.left(),
+                    Side::Right => operation.right(),
+                })
+                .map(|token| token.text().matches('\n').count())
+                .sum();
+            covered_until = Some(pair.end);
+            blocks.insert(pair.start, (pair, lines));
+        }
+        blocks
+    }
+
+    /// Maximal runs of operations which insert tokens only on the given side. Whitespace inserted
+    /// on the other side doesn't interrupt a run.
+    fn insert_runs(&self, side: Side) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = vec![];
+        let mut start = None;
+        for (index, operation) in self.operations.iter().enumerate() {
+            let in_run = match (operation, side) {
+                (AlignmentOperation::InsertLeft { .. }, Side::Left) => true,
+                (AlignmentOperation::InsertRight { .. }, Side::Right) => true,
+                (AlignmentOperation::InsertLeft { left: token }, Side::Right)
+                | (AlignmentOperation::InsertRight { right: token }, Side::Left) => {
+                    start.is_some() && token.is_whitespace()
+                }
+                _ => false,
+            };
+            match (in_run, start) {
+                (true, None) => start = Some(index),
+                (false, Some(run_start)) => {
+                    runs.push(run_start..index);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(run_start) = start {
+            runs.push(run_start..self.operations.len());
+        }
+        runs
+    }
+
+    /// Indices of inserted and deleted operations which are moved text: a deleted run paired with
+    /// an inserted run of the same tokens, ignoring whitespace. Runs are paired in order, and only
+    /// runs of at least `MIN_MOVED_TOKENS` tokens.
+    fn moved_operations(&self) -> HashSet<usize> {
+        let key = |run: &Range<usize>, side: Side| -> Vec<&str> {
+            self.operations[run.clone()]
+                .iter()
+                .filter_map(|operation| match side {
+                    Side::Left => operation.left(),
+                    Side::Right => operation.right(),
+                })
+                .filter(|token| !token.is_whitespace() && !token.is_ignored())
+                .map(|token| token.text())
+                .collect()
+        };
+        let mut inserted: Vec<_> = self
+            .insert_runs(Side::Right)
+            .into_iter()
+            .map(|run| (key(&run, Side::Right), run))
+            .filter(|(key, _)| key.len() >= MIN_MOVED_TOKENS)
+            .collect();
+        let mut moved = HashSet::new();
+        for deleted in self.insert_runs(Side::Left) {
+            let deleted_key = key(&deleted, Side::Left);
+            if deleted_key.len() < MIN_MOVED_TOKENS {
+                continue;
+            }
+            let Some(position) = inserted.iter().position(|(key, _)| *key == deleted_key) else {
+                continue;
+            };
+            let (_, inserted) = inserted.remove(position);
+            moved.extend(deleted.chain(inserted));
+        }
+        moved
+    }
+
+    /// Output lines when every token is a whole line. Deleted and changed lines are shown on the
+    /// left side only, so they are rendered like in a traditional line diff.
+    fn whole_line_output(&self, options: &RenderOptions, out: &mut dyn FnMut(OutputLine)) {
+        let text = |token: &T| token.text().trim_end_matches(['\n', '\r']).to_string();
+        let mut output = DiffLineOutput::new(options.emphasis, out);
+        for operation in &self.operations {
+            match operation {
+                AlignmentOperation::Mutation { left, right } if left.text() == right.text() => {
+                    output.add_same(&text(right))
+                }
+                AlignmentOperation::Mutation { left, right } if options.refine_words => {
+                    output.add_refined_mutation(&text(left), &text(right))
+                }
+                AlignmentOperation::Mutation { left, right } => {
+                    output.add_mutation(&text(left), &text(right))
+                }
+                AlignmentOperation::InsertLeft { left } => {
+                    output.whole_line(Side::Left, &text(left));
+                    continue;
+                }
+                AlignmentOperation::InsertRight { right } => {
+                    output.whole_line(Side::Right, &text(right));
+                    continue;
+                }
+            }
+            output.flush();
+        }
+    }
+
+    fn output_lines(&self, options: &RenderOptions) -> Vec<OutputLine> {
+        let mut lines = vec![];
+        self.stream_lines(options, &mut |line| lines.push(line));
+        lines
+    }
+
+    /// Passes the lines of the rendered diff to `out` as they are completed, without folding.
+    fn stream_lines(&self, options: &RenderOptions, out: &mut dyn FnMut(OutputLine)) {
+        if self.operations.is_empty() {
+            // Two empty documents have no lines, rather than a single empty one.
+            return;
+        }
+        if options.whole_lines {
+            self.whole_line_output(options, out);
+        } else {
+            self.word_output(options, out);
+        }
+        if let Some(side) = self.missing_newline() {
+            out(OutputLine::NoNewline { side });
+        }
+    }
+
+    /// Output lines of the word diff.
+    fn word_output(&self, options: &RenderOptions, out: &mut dyn FnMut(OutputLine)) {
+        let mut output = DiffLineOutput::new(options.emphasis, out);
         let mut prev_was_space = true;
-        for operation in self.operations.iter() {
+        let collapsible = if options.collapse_block_changes {
+            self.collapsible_blocks()
+        } else {
+            HashMap::new()
+        };
+        let moved = if options.detect_moves {
+            self.moved_operations()
+        } else {
+            HashSet::new()
+        };
+        let mut index = 0;
+        while index < self.operations.len() {
+            let operation = &self.operations[index];
+            index += 1;
+            if let Some((pair, lines)) = collapsible.get(&(index - 1)) {
+                output.collapsed_block(pair.side, *lines);
+                if pair.side == Side::Right {
+                    // Keep indentation of the line following the block.
+                    let last_whitespace = self.operations[pair.start..pair.end]
+                        .iter()
+                        .rev()
+                        .filter_map(|operation| operation.right())
+                        .find(|token| token.text().contains('\n'));
+                    if let Some(whitespace) = last_whitespace {
+                        output
+                            .insert_right_space(whitespace.text().split('\n').next_back().unwrap());
+                    }
+                }
+                prev_was_space = true;
+                index = pair.end + 1;
+                continue;
+            }
             prev_was_space = match operation {
+                AlignmentOperation::Mutation { left, right }
+                    if left.is_block_start() || left.is_block_end() =>
+                {
+                    if left.is_block_start() != right.is_block_start() {
+                        output.block_change(Side::Right, right.is_block_start());
+                    }
+                    false
+                }
+                AlignmentOperation::InsertLeft { left }
+                    if left.is_block_start() || left.is_block_end() =>
+                {
+                    output.block_change(Side::Left, left.is_block_start());
+                    false
+                }
+                AlignmentOperation::InsertRight { right }
+                    if right.is_block_start() || right.is_block_end() =>
+                {
+                    output.block_change(Side::Right, right.is_block_start());
+                    false
+                }
                 AlignmentOperation::Mutation { left, right } => {
                     // TODO: assuming here that newlines are
                     let left_text = left.text();
                     let right_text = right.text();
                     if left_text == right_text {
                         output.add_same(right_text);
+                    } else if options.show_case_changes
+                        && left_text.to_lowercase() == right_text.to_lowercase()
+                    {
+                        output.add_case_change(left_text, right_text);
+                    } else if options.shows_as_same(left_text, right_text) {
+                        output.add_same(right_text);
+                    } else if options.refine_words && left.is_word() && right.is_word() {
+                        output.add_refined_mutation(left_text, right_text);
                     } else {
                         output.add_mutation(left_text, right_text);
                     }
                     false
                 }
+                AlignmentOperation::InsertLeft { left } if left.is_ignored() => prev_was_space,
+                AlignmentOperation::InsertRight { right } if right.is_ignored() => {
+                    output.add_same(right.text());
+                    prev_was_space
+                }
                 AlignmentOperation::InsertLeft { left } => {
                     if left.is_whitespace() {
                         // Ignoring whitespace for left
                         if !prev_was_space {
-                            output.insert_left(" ")
+                            if moved.contains(&(index - 1)) {
+                                output.move_left(" ")
+                            } else {
+                                output.insert_left(" ")
+                            }
                         }
                         true
+                    } else if moved.contains(&(index - 1)) {
+                        output.move_left(left.text());
+                        false
                     } else {
                         output.insert_left(left.text());
                         false
@@ -376,11 +2737,21 @@ impl<'a, T: Token> Alignment<'a, T> {
                     if right.is_whitespace() {
                         // TODO: handle whitespace
                         let whitespace = right.text();
-                        if whitespace.contains('\n') {
-                            let mut whitespace = whitespace.split('\n');
-                            let first = whitespace.next().unwrap();
+                        if let Some((first, rest)) = whitespace.split_once('\n') {
                             output.insert_right_space(first);
-                            for space in whitespace {
+                            // Lines between the first and the last one are blank. The last one
+                            // is always kept, as it's the indentation of the next line, or empty
+                            // after a line break at the end of the document.
+                            let (last, blank) = match rest.rsplit_once('\n') {
+                                Some((blank, last)) => (last, blank.split('\n').collect()),
+                                None => (rest, vec![]),
+                            };
+                            let blank = if options.squeeze_blank {
+                                &blank[..blank.len().min(1)]
+                            } else {
+                                &blank[..]
+                            };
+                            for space in blank.iter().chain([&last]) {
                                 output.flush();
                                 output.insert_right_space(space);
                             }
@@ -388,6 +2759,9 @@ impl<'a, T: Token> Alignment<'a, T> {
                             output.insert_right_space(whitespace);
                         }
                         true
+                    } else if moved.contains(&(index - 1)) {
+                        output.move_right(right.text());
+                        false
                     } else {
                         output.insert_right(right.text());
                         false
@@ -396,24 +2770,58 @@ impl<'a, T: Token> Alignment<'a, T> {
             }
         }
         output.flush();
-        output.output()
     }
-    pub fn pretty(&self) {
-        for line in self.output_lines() {
-            match line {
-                OutputLine::Same { line } => {
-                    println!("  {}", line);
-                }
-                OutputLine::Change { left, right } => {
-                    if let Some(left) = left {
-                        println!("- {}", &left);
-                    }
-                    if let Some(right) = right {
-                        println!("+ {}", &right);
-                    }
-                }
+    /// Lines of the rendered diff, with unchanged lines folded if `options.context` is set, and
+    /// filtered by `options.only`.
+    pub fn lines(&self, options: &RenderOptions) -> impl Iterator<Item = OutputLine> {
+        let mut lines = self.output_lines(options);
+        if let Some(context) = options.context {
+            lines = fold(lines, context);
+        }
+        let only = options.only;
+        lines
+            .into_iter()
+            .filter_map(move |line| only_changes(line, only))
+    }
+
+    /// The pretty diff as a string, one `\n` terminated line per output line.
+    pub fn render(&self, options: &RenderOptions) -> String {
+        self.lines(options)
+            .map(|line| render_line(options, line))
+            .collect()
+    }
+
+    /// Writes the pretty diff to `writer`. Without folding, each line is written as soon as it's
+    /// complete, so the output is never held in memory as a whole.
+    pub fn write_pretty(&self, options: &RenderOptions, writer: &mut dyn Write) -> io::Result<()> {
+        if options.context.is_some() {
+            // Folding needs the distance of each line to the next change.
+            for line in self.lines(options) {
+                writer.write_all(render_line(options, line).as_bytes())?;
             }
+            return Ok(());
         }
+        let mut result = Ok(());
+        self.stream_lines(options, &mut |line| {
+            let Some(line) = only_changes(line, options.only) else {
+                return;
+            };
+            if result.is_ok() {
+                result = writer.write_all(render_line(options, line).as_bytes());
+            }
+        });
+        result
+    }
+
+    /// Prints the pretty diff.
+    pub fn pretty(&self, options: &RenderOptions) {
+        self.write_pretty(options, &mut io::stdout().lock())
+            .expect("failed printing to stdout");
+    }
+
+    /// Prints the diff with `options.side_by_side` set to `width`.
+    pub fn side_by_side(&self, options: &RenderOptions, width: usize) {
+        self.pretty(&options.clone().with_side_by_side(Some(width)))
     }
 
     pub fn interleave_tokens(mut self, left: &'a [T], right: &'a [T]) -> Self {
@@ -461,3 +2869,340 @@ impl<'a, T: Token> Alignment<'a, T> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scoring::AffineScoring;
+    use crate::tokenizer::{Token as TextToken, TokenType};
+    use crate::Tokens;
+
+    type Tokenized<'a> = Vec<TextToken<'a, TokenType>>;
+    /// Offset and text of a token.
+    type Position<'a> = (usize, &'a str);
+
+    /// Offsets and texts of the tokens of each operation, which identify them exactly.
+    fn positions<'a>(
+        alignment: &Alignment<'_, TextToken<'a, TokenType>>,
+    ) -> Vec<(Option<Position<'a>>, Option<Position<'a>>)> {
+        alignment
+            .operations()
+            .iter()
+            .map(|operation| {
+                let position =
+                    |token: &&TextToken<'a, TokenType>| (token.start(), token.source_text());
+                (
+                    operation.left().map(position),
+                    operation.right().map(position),
+                )
+            })
+            .collect()
+    }
+
+    const FIXTURES: [(&str, &str); 5] = [
+        (
+            include_str!("../test/base.rs"),
+            include_str!("../test/change.rs"),
+        ),
+        (
+            include_str!("../test/moved_base.rs"),
+            include_str!("../test/moved_change.rs"),
+        ),
+        (
+            include_str!("../test/nested_base.py"),
+            include_str!("../test/nested_change.py"),
+        ),
+        (
+            include_str!("../test/reindent_base.py"),
+            include_str!("../test/reindent_change.py"),
+        ),
+        (
+            include_str!("../test/snap_base.rs"),
+            include_str!("../test/snap_change.rs"),
+        ),
+    ];
+
+    /// Xorshift generator, so the random inputs are the same in every run.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    /// Pairs of random texts from a small vocabulary, the right ones with a few words deleted,
+    /// replaced or inserted, so there are many equally good alignments to choose from.
+    fn random_texts(count: usize, max_length: usize) -> Vec<(String, String)> {
+        const WORDS: [&str; 10] = ["a", "b", "c", "fn", "x", "(", ")", "{", "}", "\n"];
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        (0..count)
+            .map(|_| {
+                let length = random.below(max_length);
+                let left: Vec<&str> = (0..length).map(|_| WORDS[random.below(10)]).collect();
+                let mut right = vec![];
+                for word in &left {
+                    match random.below(10) {
+                        0 => {}
+                        1 => right.push(WORDS[random.below(10)]),
+                        2 => right.extend([WORDS[random.below(10)], word]),
+                        _ => right.push(word),
+                    }
+                }
+                (left.join(" "), right.join(" "))
+            })
+            .collect()
+    }
+
+    /// Checks that `aligner` finds the same operations with the same score as `align` on the
+    /// fixtures and on the `random` texts.
+    fn assert_same_as_align(
+        random: &[(String, String)],
+        aligner: impl for<'t> Fn(
+            &AffineScoring,
+            &'t Tokenized,
+            &'t Tokenized,
+        ) -> Alignment<'t, TextToken<'t, TokenType>>,
+    ) {
+        let scoring = AffineScoring::default();
+        let inputs = FIXTURES.iter().map(|(left, right)| (*left, *right)).chain(
+            random
+                .iter()
+                .map(|(left, right)| (left.as_str(), right.as_str())),
+        );
+        for (left, right) in inputs {
+            let (left_tokens, right_tokens) = (Tokens::new(left).tokens, Tokens::new(right).tokens);
+            let expected = align(&scoring, &left_tokens, &right_tokens);
+            let actual = aligner(&scoring, &left_tokens, &right_tokens);
+            assert_eq!(
+                positions(&actual),
+                positions(&expected),
+                "{:?} {:?}",
+                left,
+                right
+            );
+            assert_eq!(actual.score(), expected.score(), "{:?} {:?}", left, right);
+        }
+    }
+
+    /// Whether the operations are of the same kinds and refer to the same tokens.
+    fn same_operations(
+        left: &[AlignmentOperation<&TextToken<TokenType>>],
+        right: &[AlignmentOperation<&TextToken<TokenType>>],
+    ) -> bool {
+        let same =
+            |x: Option<&&TextToken<TokenType>>, y: Option<&&TextToken<TokenType>>| match (x, y) {
+                (Some(x), Some(y)) => std::ptr::eq(*x, *y),
+                (x, y) => x.is_none() && y.is_none(),
+            };
+        left.len() == right.len()
+            && left
+                .iter()
+                .zip(right)
+                .all(|(x, y)| same(x.left(), y.left()) && same(x.right(), y.right()))
+    }
+
+    #[test]
+    fn paths_share_back_pointers() {
+        let scoring = AffineScoring::default();
+        let left = Tokens::new(include_str!("../test/base.rs")).tokens;
+        let right = Tokens::new(include_str!("../test/change.rs")).tokens;
+        let (pointers, last) = back_pointers(&scoring, &left, &right, ScoreState::start(), &mut ());
+        let width = left.len() + 1;
+        let mut reads = 0;
+        // Paths ending in each state, traced back through the same back pointers.
+        let paths: Vec<(Vec<_>, f64)> = [
+            (LastOperation::Mutation, last.last_was_mutation),
+            (LastOperation::InsertLeft, last.last_was_insert_left),
+            (LastOperation::InsertRight, last.last_was_insert_right),
+        ]
+        .into_iter()
+        .map(|(state, score)| {
+            let operations = trace_back(&left, &right, state, |i, j| {
+                reads += 1;
+                Some(pointers[i * width + j])
+            })
+            .unwrap();
+            (operations, score)
+        })
+        .collect();
+        // One read of the back pointers per operation, without copying any path.
+        assert_eq!(
+            reads,
+            paths
+                .iter()
+                .map(|(operations, _)| operations.len())
+                .sum::<usize>()
+        );
+        for (operations, score) in &paths {
+            let lefts: Vec<_> = operations.iter().filter_map(|x| x.left()).collect();
+            let rights: Vec<_> = operations.iter().filter_map(|x| x.right()).collect();
+            assert!(lefts.iter().zip(&left).all(|(x, y)| std::ptr::eq(**x, y)));
+            assert!(rights.iter().zip(&right).all(|(x, y)| std::ptr::eq(**x, y)));
+            assert_eq!((lefts.len(), rights.len()), (left.len(), right.len()));
+            assert!((operations_cost(&scoring, operations) - score).abs() < 1e-9);
+        }
+        let alignment = align(&scoring, &left, &right);
+        assert!(paths
+            .iter()
+            .any(|(operations, _)| same_operations(operations, alignment.operations())));
+    }
+
+    /// Anchors are a heuristic which can pair a unique line the best alignment doesn't, but on
+    /// the fixtures the anchored alignment is as good as the unanchored one. It can differ in ties.
+    #[test]
+    fn line_anchors_are_as_good_as_align() {
+        let scoring = AffineScoring::default();
+        for (left, right) in FIXTURES {
+            let (left_tokens, right_tokens) = (Tokens::new(left).tokens, Tokens::new(right).tokens);
+            let index = |tokens: &Tokenized, offset: usize| {
+                tokens.partition_point(|token| token.start() < offset)
+            };
+            let splits: Vec<_> = crate::anchors::unique_line_anchors(left, right)
+                .into_iter()
+                .map(|(left, right)| (index(&left_tokens, left), index(&right_tokens, right)))
+                .collect();
+            let anchored = align_segments(
+                |left, right| align(&scoring, left, right),
+                &left_tokens,
+                &right_tokens,
+                &splits,
+            );
+            let expected = align(&scoring, &left_tokens, &right_tokens);
+            let cost = operations_cost(&scoring, anchored.operations());
+            assert!(
+                (cost - expected.score()).abs() < 1e-9,
+                "{:?} {:?}",
+                left,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn empty_inputs() {
+        let scoring = AffineScoring::default();
+        let empty: Tokenized = vec![];
+        let text = Tokens::new("fn main() {}").tokens;
+        let word = Tokens::new("main").tokens;
+
+        let alignment = align(&scoring, &empty, &empty);
+        assert!(alignment.operations().is_empty());
+        assert_eq!(alignment.score(), 0.);
+
+        let alignment = align(&scoring, &empty, &text);
+        assert_eq!(alignment.operations().len(), text.len());
+        assert!(alignment
+            .operations()
+            .iter()
+            .all(|operation| matches!(operation, AlignmentOperation::InsertRight { .. })));
+        assert_eq!(
+            alignment.score(),
+            operations_cost(&scoring, alignment.operations())
+        );
+
+        let alignment = align(&scoring, &text, &empty);
+        assert_eq!(alignment.operations().len(), text.len());
+        assert!(alignment
+            .operations()
+            .iter()
+            .all(|operation| matches!(operation, AlignmentOperation::InsertLeft { .. })));
+        assert_eq!(
+            alignment.score(),
+            operations_cost(&scoring, alignment.operations())
+        );
+
+        let alignment = align(&scoring, &word, &word);
+        assert_eq!(
+            positions(&alignment),
+            [(Some((0, "main")), Some((0, "main")))]
+        );
+        assert_eq!(alignment.score(), 0.);
+
+        let alignment = align(&scoring, &word, &empty);
+        assert_eq!(positions(&alignment), [(Some((0, "main")), None)]);
+        assert_eq!(alignment.score(), crate::scoring::DEFAULT_START_INSERT);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let budget = 64 * 1024 * 1024;
+        assert!(check_memory_budget(estimate_memory(1000, 1000), budget).is_ok());
+        // A million tokens on each side need a back pointer for each of 10^12 cells.
+        let estimated = estimate_memory(1_000_000, 1_000_000);
+        assert!(estimated.unwrap() > budget);
+        match check_memory_budget(estimated, budget) {
+            Err(AlignmentError::MemoryBudgetExceeded {
+                estimated: Some(_),
+                budget: 67108864,
+            }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        let error = check_memory_budget(estimate_memory(usize::MAX, 2), budget).unwrap_err();
+        assert!(matches!(
+            error,
+            AlignmentError::MemoryBudgetExceeded {
+                estimated: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "alignment would need more memory than can be addressed (budget is 64 MiB)"
+        );
+        assert!(check_memory_budget(estimate_low_memory(1_000_000, 1_000_000), budget).is_err());
+        assert!(check_memory_budget(estimate_linear_memory(1000), budget).is_ok());
+    }
+
+    #[test]
+    fn linear_is_same_as_align() {
+        assert_same_as_align(&random_texts(200, 40), |scoring, left, right| {
+            align_linear(scoring, left, right)
+        });
+    }
+
+    #[test]
+    fn low_memory_is_same_as_align() {
+        assert_same_as_align(&random_texts(200, 40), |scoring, left, right| {
+            align_low_memory(scoring, left, right)
+        });
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_is_same_as_align() {
+        // Long texts, so anti-diagonals are split between threads.
+        let random = [random_texts(200, 40), random_texts(4, 2000)].concat();
+        assert_same_as_align(&random, |scoring, left, right| {
+            align_parallel(scoring, left, right)
+        });
+    }
+
+    #[test]
+    fn banded_is_same_as_align() {
+        let random = random_texts(200, 40);
+        for band in [1, 2, 8] {
+            assert_same_as_align(&random, |scoring, left, right| {
+                align_banded(scoring, left, right, band)
+            });
+        }
+    }
+
+    #[test]
+    fn narrow_band_is_used_for_few_changes() {
+        let scoring = AffineScoring::default();
+        let left = include_str!("../test/base.rs");
+        let right = left.replacen("fn", "pub fn", 1);
+        let (left, right) = (Tokens::new(left).tokens, Tokens::new(&right).tokens);
+        let mut stats = AlignmentStats::default();
+        let alignment = align_banded_with_stats(&scoring, &left, &right, 2, &mut stats);
+        assert!(stats.cells < (left.len() + 1) * (right.len() + 1) / 10);
+        assert_eq!(
+            positions(&alignment),
+            positions(&align(&scoring, &left, &right))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+/// Byte offsets of lines, paired with their content without the line ending.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        Some((start, line.trim_end_matches(['\n', '\r'])))
+    })
+}
+
+/// Finds lines that are identical and occur exactly once on both sides, and keeps the longest
+/// chain of them that is in the same order on both sides (as in patience diff). Blank lines are
+/// never used as anchors. Returns pairs of byte offsets of the starts of the anchored lines.
+pub fn unique_line_anchors(left: &str, right: &str) -> Vec<(usize, usize)> {
+    // line -> (occurrences in left, occurrences in right, left offset, right offset)
+    let mut occurrences: HashMap<&str, (usize, usize, usize, usize)> = HashMap::new();
+    for (offset, line) in lines(left).filter(|(_, line)| !line.trim().is_empty()) {
+        let entry = occurrences.entry(line).or_insert((0, 0, offset, 0));
+        entry.0 += 1;
+    }
+    for (offset, line) in lines(right) {
+        if let Some(entry) = occurrences.get_mut(line) {
+            entry.1 += 1;
+            entry.3 = offset;
+        }
+    }
+    let mut candidates: Vec<(usize, usize)> = occurrences
+        .into_values()
+        .filter(|&(left_count, right_count, _, _)| left_count == 1 && right_count == 1)
+        .map(|(_, _, left_offset, right_offset)| (left_offset, right_offset))
+        .collect();
+    candidates.sort();
+    longest_increasing_chain(&candidates)
+}
+
+/// Byte offset of the end of the line starting at `offset`, before the line ending.
+pub fn line_end(text: &str, offset: usize) -> usize {
+    let line = text[offset..].split('\n').next().unwrap_or("");
+    offset + line.trim_end_matches('\r').len()
+}
+
+/// Longest subsequence of `pairs` (sorted by the first element) that is increasing in the second
+/// element, using patience sorting.
+fn longest_increasing_chain(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // Index into `pairs` of the top card of each pile.
+    let mut piles: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = Vec::with_capacity(pairs.len());
+    for (index, &(_, right)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].1 < right);
+        previous.push(if pile > 0 {
+            Some(piles[pile - 1])
+        } else {
+            None
+        });
+        if pile == piles.len() {
+            piles.push(index);
+        } else {
+            piles[pile] = index;
+        }
+    }
+    let mut chain = vec![];
+    let mut current = piles.last().copied();
+    while let Some(index) = current {
+        chain.push(pairs[index]);
+        current = previous[index];
+    }
+    chain.reverse();
+    chain
+}
@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use crate::matrix::similarity;
+use crate::types::{AlignmentScoring, Token};
+
+/// Blocks with lower similarity are never paired, and are shown as removed and added instead.
+pub const MIN_BLOCK_SIMILARITY: f64 = 0.5;
+
+/// Splits tokens into top-level units: every line at indentation level 0 starts a new unit,
+/// except lines starting with a closing bracket directly after a block, which close that block
+/// (e.g. `}` of a function). Block tokens track the level, so unbalanced structure is tolerated:
+/// block ends at level 0 are ignored, and a block which is never closed extends to the end.
+pub fn top_level_units<T: Token>(tokens: &[T]) -> Vec<Range<usize>> {
+    let mut starts = vec![];
+    let mut depth = 0usize;
+    let mut after_block = false;
+    for (index, token) in tokens.iter().enumerate() {
+        if token.is_block_start() {
+            depth += 1;
+            continue;
+        }
+        if token.is_block_end() {
+            depth = depth.saturating_sub(1);
+            after_block = depth == 0;
+            continue;
+        }
+        let closes_block = after_block && matches!(token.text(), "}" | ")" | "]");
+        if depth == 0 && (index == 0 || token.is_line_start()) && !closes_block {
+            starts.push(index);
+        }
+        after_block = false;
+    }
+    // Block tokens before the first unit belong to it.
+    if let Some(first) = starts.first_mut() {
+        *first = 0;
+    }
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain(std::iter::once(&tokens.len())))
+        .map(|(&start, &end)| start..end)
+        .collect()
+}
+
+/// Pairs units of both sides in order, maximizing the total similarity of the pairs. Only units
+/// with similarity at least `MIN_BLOCK_SIMILARITY` are paired.
+pub fn pair_units<T, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &[T],
+    left_units: &[Range<usize>],
+    right: &[T],
+    right_units: &[Range<usize>],
+) -> Vec<(usize, usize)> {
+    let (n, m) = (left_units.len(), right_units.len());
+    // `best[i][j]` is the best total similarity of pairs among the first `i` left and `j` right
+    // units.
+    let mut best = vec![vec![0f64; m + 1]; n + 1];
+    let mut paired = vec![vec![false; m + 1]; n + 1];
+    for i in 1..=n {
+        for j in 1..=m {
+            best[i][j] = best[i - 1][j].max(best[i][j - 1]);
+            let pair = similarity(
+                scoring,
+                &left[left_units[i - 1].clone()],
+                &right[right_units[j - 1].clone()],
+            );
+            if pair >= MIN_BLOCK_SIMILARITY && best[i - 1][j - 1] + pair > best[i][j] {
+                best[i][j] = best[i - 1][j - 1] + pair;
+                paired[i][j] = true;
+            }
+        }
+    }
+    let mut pairs = vec![];
+    let (mut i, mut j) = (n, m);
+    while i > 0 && j > 0 {
+        if paired[i][j] {
+            pairs.push((i - 1, j - 1));
+            i -= 1;
+            j -= 1;
+        } else if best[i - 1][j] >= best[i][j - 1] {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+/// Splits for `align_segments` which align paired units with each other, and make unpaired units
+/// entirely removed or added.
+pub fn block_splits<T: Token, S: AlignmentScoring<T>>(
+    scoring: &S,
+    left: &[T],
+    right: &[T],
+) -> Vec<(usize, usize)> {
+    let left_units = top_level_units(left);
+    let right_units = top_level_units(right);
+    let pairs = pair_units(scoring, left, &left_units, right, &right_units);
+    let mut splits = vec![];
+    let mut right_end = 0;
+    for (l, r) in pairs
+        .into_iter()
+        .map(|(l, r)| (left_units[l].clone(), right_units[r].clone()))
+        .chain(std::iter::once((
+            left.len()..left.len(),
+            right.len()..right.len(),
+        )))
+    {
+        // Unpaired units before this pair: first the removed ones, then the added ones.
+        splits.push((l.start, right_end));
+        splits.push((l.start, r.start));
+        splits.push((l.end, r.end));
+        right_end = r.end;
+    }
+    splits
+}
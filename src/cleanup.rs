@@ -0,0 +1,253 @@
+use crate::alignment::{operations_cost, Alignment, AlignmentOperation};
+use crate::types::{AlignmentScoring, Token};
+
+/// Costs closer than this are considered equal.
+const COST_EPSILON: f64 = 1e-9;
+
+/// Whether the tokens are the same, which for block tokens also means the same kind.
+pub(crate) fn same<T: Token>(left: &T, right: &T) -> bool {
+    left.text() == right.text()
+        && left.is_block_start() == right.is_block_start()
+        && left.is_block_end() == right.is_block_end()
+}
+
+fn is_equality<T: Token>(operation: &AlignmentOperation<&T>) -> bool {
+    matches!(operation, AlignmentOperation::Mutation { left, right } if same(*left, *right))
+}
+
+/// Length of a token in characters. Block tokens count as a line break.
+fn length<T: Token>(token: &T) -> usize {
+    if token.is_block_start() || token.is_block_end() {
+        1
+    } else {
+        token.text().chars().count()
+    }
+}
+
+/// Maximal runs of equalities and of edits, as ranges of operation indices.
+fn runs<T: Token>(operations: &[AlignmentOperation<&T>]) -> Vec<(bool, std::ops::Range<usize>)> {
+    let mut runs: Vec<(bool, std::ops::Range<usize>)> = vec![];
+    for (index, operation) in operations.iter().enumerate() {
+        let equal = is_equality(operation);
+        match runs.last_mut() {
+            Some((run_equal, range)) if *run_equal == equal => range.end = index + 1,
+            _ => runs.push((equal, index..index + 1)),
+        }
+    }
+    runs
+}
+
+/// Larger of deleted and inserted length of an edit run.
+fn edit_length<T: Token>(operations: &[AlignmentOperation<&T>]) -> usize {
+    let deleted: usize = operations
+        .iter()
+        .filter_map(|x| x.left())
+        .map(|x| length(*x))
+        .sum();
+    let inserted: usize = operations
+        .iter()
+        .filter_map(|x| x.right())
+        .map(|x| length(*x))
+        .sum();
+    deleted.max(inserted)
+}
+
+/// Score of a boundary between two tokens, higher is a more natural place for an edit to start
+/// or end. Line and block boundaries are best, then transitions between words and other tokens.
+fn boundary_score<T: Token>(before: Option<&T>, after: Option<&T>) -> usize {
+    match (before, after) {
+        (None, _) | (_, None) => 4,
+        (Some(before), Some(after)) => {
+            if before.is_block_start()
+                || before.is_block_end()
+                || after.is_block_start()
+                || after.is_block_end()
+                || after.is_line_start()
+            {
+                3
+            } else if before.is_word() != after.is_word() {
+                2
+            } else {
+                0
+            }
+        }
+    }
+}
+
+impl<'a, T: Token> Alignment<'a, T> {
+    /// Semantic cleanup similar to the one of diff-match-patch. It should be run before
+    /// whitespace is interleaved back. First, equalities which are not longer than the edits on
+    /// both of their sides are turned into edits, and the merged edit regions become deletions
+    /// followed by insertions. Then, single sided edits which can slide along equal tokens are
+    /// moved to the most natural token boundary.
+    pub fn cleanup_semantic(&mut self) {
+        self.eliminate_short_equalities();
+        self.shift_edits_to_boundaries(|_, _| true);
+    }
+
+    fn eliminate_short_equalities(&mut self) {
+        let operations = self.operations_mut();
+        loop {
+            let runs = runs(operations);
+            let eliminated = runs.windows(3).find(|window| {
+                let [(false, before), (true, equal), (false, after)] = window else {
+                    return false;
+                };
+                let equal_length: usize = operations[equal.clone()]
+                    .iter()
+                    .filter_map(|x| x.left())
+                    .map(|x| length(*x))
+                    .sum();
+                equal_length <= edit_length(&operations[before.clone()])
+                    && equal_length <= edit_length(&operations[after.clone()])
+            });
+            let Some([(_, before), _, (_, after)]) = eliminated else {
+                break;
+            };
+            let region = before.start..after.end;
+            let left: Vec<_> = operations[region.clone()]
+                .iter()
+                .filter_map(|x| x.left().copied())
+                .map(|left| AlignmentOperation::InsertLeft { left })
+                .collect();
+            let right: Vec<_> = operations[region.clone()]
+                .iter()
+                .filter_map(|x| x.right().copied())
+                .map(|right| AlignmentOperation::InsertRight { right })
+                .collect();
+            operations.splice(region, left.into_iter().chain(right));
+        }
+    }
+
+    /// Moves inserted and deleted runs to the most natural token type boundary among the positions
+    /// with the same cost, so the diff is easier to read without being any worse.
+    pub fn snap_boundaries<S: AlignmentScoring<T>>(&mut self, scoring: &S) {
+        self.shift_edits_to_boundaries(|old, new| {
+            (operations_cost(scoring, old) - operations_cost(scoring, new)).abs() < COST_EPSILON
+        });
+    }
+
+    /// Slides single sided edits along equal tokens to the position with the best boundary score,
+    /// among the positions `accept` allows. `accept` gets the old and new operations around the
+    /// edit, including one operation on each side.
+    fn shift_edits_to_boundaries(
+        &mut self,
+        accept: impl Fn(&[AlignmentOperation<&'a T>], &[AlignmentOperation<&'a T>]) -> bool,
+    ) {
+        let operations = self.operations_mut();
+        for (equal, range) in runs(operations) {
+            if equal {
+                continue;
+            }
+            let left_only = operations[range.clone()]
+                .iter()
+                .all(|x| matches!(x, AlignmentOperation::InsertLeft { .. }));
+            let right_only = operations[range.clone()]
+                .iter()
+                .all(|x| matches!(x, AlignmentOperation::InsertRight { .. }));
+            if !left_only && !right_only {
+                continue;
+            }
+            // Tokens of the edited side, with the equalities around the edit.
+            let side = |x: &AlignmentOperation<&'a T>| -> &'a T {
+                match x {
+                    AlignmentOperation::Mutation { left, right } => {
+                        if left_only {
+                            left
+                        } else {
+                            right
+                        }
+                    }
+                    AlignmentOperation::InsertLeft { left } => left,
+                    AlignmentOperation::InsertRight { right } => right,
+                }
+            };
+            let mut start = range.start;
+            while start > 0
+                && is_equality(&operations[start - 1])
+                && same(
+                    side(&operations[start - 1]),
+                    side(&operations[start - 1 + range.len()]),
+                )
+            {
+                start -= 1;
+            }
+            let mut end = range.start;
+            while end + range.len() < operations.len()
+                && is_equality(&operations[end + range.len()])
+                && same(side(&operations[end + range.len()]), side(&operations[end]))
+            {
+                end += 1;
+            }
+            if start == end {
+                continue;
+            }
+            let window = start..end + range.len();
+            // Tokens of the edited side in the window, in the order they appear.
+            let tokens: Vec<&'a T> = operations[window.clone()].iter().map(side).collect();
+            // Equal tokens of the other side stay in place, the edit is moved among them.
+            let other: Vec<&'a T> = operations[window.clone()]
+                .iter()
+                .filter(|x| is_equality(x))
+                .map(|x| match x {
+                    AlignmentOperation::Mutation { left, right } => {
+                        if left_only {
+                            *right
+                        } else {
+                            *left
+                        }
+                    }
+                    _ => unreachable!(),
+                })
+                .collect();
+            let moved = |best: usize| -> Vec<AlignmentOperation<&'a T>> {
+                let mut other = other.iter().copied();
+                tokens
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, &token)| {
+                        let in_edit = offset >= best && offset < best + range.len();
+                        match (in_edit, left_only) {
+                            (true, true) => AlignmentOperation::InsertLeft { left: token },
+                            (true, false) => AlignmentOperation::InsertRight { right: token },
+                            (false, true) => AlignmentOperation::Mutation {
+                                left: token,
+                                right: other.next().unwrap(),
+                            },
+                            (false, false) => AlignmentOperation::Mutation {
+                                left: other.next().unwrap(),
+                                right: token,
+                            },
+                        }
+                    })
+                    .collect()
+            };
+            let score = |offset: usize| {
+                let before = (start + offset)
+                    .checked_sub(1)
+                    .map(|index| side(&operations[index]));
+                let after = operations.get(start + offset + range.len()).map(side);
+                boundary_score(before, tokens.get(offset).copied())
+                    + boundary_score(tokens.get(offset + range.len() - 1).copied(), after)
+            };
+            // Operations around the window, which are compared with `accept` as well.
+            let around = window.start.saturating_sub(1)..(window.end + 1).min(operations.len());
+            let accepted = |offset: usize| {
+                let mut new = operations[around.start..window.start].to_vec();
+                new.extend(moved(offset));
+                new.extend_from_slice(&operations[window.end..around.end]);
+                accept(&operations[around.clone()], &new)
+            };
+            // On ties, the edit stays where the alignment put it.
+            let best = (0..=(end - start))
+                .filter(|&offset| start + offset == range.start || accepted(offset))
+                .max_by_key(|&offset| (score(offset), start + offset == range.start))
+                .unwrap();
+            if start + best == range.start {
+                continue;
+            }
+            let moved = moved(best);
+            operations.splice(window, moved);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use colored::{ColoredString, Colorize};
+
+use crate::alignment::{Alignment, RenderOptions};
+use crate::runs::Class;
+use crate::types::Token;
+
+/// Text of a run with `style` applied to each line separately, so colors don't continue over line
+/// breaks (e.g. into the prefixes of pagers).
+fn styled(text: &str, style: fn(&str) -> ColoredString) -> String {
+    text.split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                style(line).to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl<'a, T: Token> Alignment<'a, T> {
+    /// Right document as running text like `git diff --color-words`, with deleted tokens in red
+    /// struck through and inserted ones in green, without line markers. Whitespace of the right
+    /// document is kept as it is, deleted whitespace is shown as a single space. Tokens which
+    /// `options` ignore, like case changes with `ignore_case`, are not colored.
+    pub fn color_words(&self, options: &RenderOptions) -> String {
+        let mut out = String::new();
+        for (class, text) in self.runs(options) {
+            match class {
+                Class::Same => out.push_str(&text),
+                Class::Deleted => out.push_str(&styled(&text, |x| x.red().strikethrough())),
+                Class::Inserted => out.push_str(&styled(&text, |x| x.green())),
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+/// Parses a config file of `key = value` lines. Empty lines and lines starting with `#` are
+/// skipped.
+pub fn parse(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut out = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", index + 1))?;
+        out.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Ok(out)
+}
@@ -0,0 +1,55 @@
+use crate::alignment::{Alignment, RenderOptions};
+use crate::runs::Class;
+use crate::types::Token;
+
+/// Default styles of the HTML output, so it renders on its own in a browser.
+pub const HTML_STYLE: &str = "<style>
+pre.diff { font-family: monospace; padding: 0.5em; background: #fafafa; }
+pre.diff .del { color: #a00; background: #fdd; text-decoration: line-through; }
+pre.diff .ins { color: #070; background: #dfd; }
+</style>
+";
+
+/// Escapes text for HTML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl<'a, T: Token> Alignment<'a, T> {
+    /// Self-contained HTML document fragment with the right document in a `<pre>` block, where
+    /// deleted text is wrapped in `<span class="del">` and inserted text in `<span class="ins">`.
+    /// Like in the pretty output, deleted whitespace is shown as a single space, and tokens which
+    /// `options` ignore are unchanged.
+    pub fn html(&self, options: &RenderOptions) -> String {
+        let mut out = String::from(HTML_STYLE);
+        out.push_str("<pre class=\"diff\">");
+        for (class, text) in self
+            .runs(options)
+            .iter()
+            .filter(|(_, text)| !text.is_empty())
+        {
+            match class {
+                Class::Same => out.push_str(&escape(text)),
+                Class::Deleted => {
+                    out.push_str(&format!("<span class=\"del\">{}</span>", escape(text)))
+                }
+                Class::Inserted => {
+                    out.push_str(&format!("<span class=\"ins\">{}</span>", escape(text)))
+                }
+            }
+        }
+        out.push_str("</pre>\n");
+        out
+    }
+}
@@ -0,0 +1,44 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::tokenizer::{Symbol, Token};
+
+/// Maps distinct token texts to `u32` ids, so the aligner compares tokens by id instead of by
+/// text. Ids are only comparable between tokens interned by the same interner, so both documents
+/// of a diff need to share one.
+#[derive(Debug, Default)]
+pub struct Interner<'a> {
+    ids: HashMap<Cow<'a, str>, u32>,
+}
+
+impl<'a> Interner<'a> {
+    /// Id of `text`, which is new if the text wasn't interned before.
+    pub fn intern(&mut self, text: &'a str) -> u32 {
+        self.intern_with(text, |_| Cow::Borrowed(text))
+    }
+
+    /// Like `intern`, with `key` making the text to keep when it's new, so texts which aren't
+    /// borrowed from the documents are copied only once.
+    fn intern_with(&mut self, text: &str, key: impl FnOnce(&str) -> Cow<'a, str>) -> u32 {
+        if let Some(&id) = self.ids.get(text) {
+            return id;
+        }
+        let id = self.ids.len() as u32;
+        self.ids.insert(key(text), id);
+        id
+    }
+
+    /// Sets the symbols of the text and of the lowercase text of the tokens.
+    pub fn intern_tokens<T>(&mut self, tokens: &mut [Token<'a, T>]) {
+        for token in tokens {
+            let text = self.intern(token.source_text());
+            let lowercase = match &token.lowercase {
+                Cow::Borrowed(lowercase) => self.intern(lowercase),
+                Cow::Owned(lowercase) => {
+                    self.intern_with(lowercase, |lowercase| Cow::Owned(lowercase.to_string()))
+                }
+            };
+            token.symbol = Some(Symbol { text, lowercase });
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::alignment::{Alignment, AlignmentOperation};
+use crate::types::Token;
+
+impl<'a, T: Token> Alignment<'a, T> {
+    /// Tokens of the left side which are aligned to a token with the same text, in order.
+    pub fn lcs(&self) -> Vec<&'a T> {
+        self.operations()
+            .iter()
+            .filter_map(|operation| match operation {
+                AlignmentOperation::Mutation { left, right } if left.text() == right.text() => {
+                    Some(*left)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Longest common subsequence of the two token streams, comparing the text of tokens. Unlike
+/// `Alignment::lcs`, this maximizes the number of matched tokens, ignoring the scoring.
+pub fn token_lcs<'a, T: Token>(left: &'a [T], right: &[T]) -> Vec<&'a T> {
+    // `lengths[l][r]` is the length of the LCS of `left[l..]` and `right[r..]`.
+    let mut lengths = vec![vec![0u32; right.len() + 1]; left.len() + 1];
+    for l in (0..left.len()).rev() {
+        for r in (0..right.len()).rev() {
+            lengths[l][r] = if left[l].text() == right[r].text() {
+                lengths[l + 1][r + 1] + 1
+            } else {
+                lengths[l + 1][r].max(lengths[l][r + 1])
+            };
+        }
+    }
+    let mut out = Vec::with_capacity(lengths[0][0] as usize);
+    let (mut l, mut r) = (0, 0);
+    while l < left.len() && r < right.len() {
+        if left[l].text() == right[r].text() {
+            out.push(&left[l]);
+            l += 1;
+            r += 1;
+        } else if lengths[l + 1][r] >= lengths[l][r + 1] {
+            l += 1;
+        } else {
+            r += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{align, AffineScoring, Tokens};
+
+    /// LCS of texts of space separated tokens, as a text again.
+    fn lcs(left: &str, right: &str) -> String {
+        let (left, right) = (Tokens::new(left), Tokens::new(right));
+        let lcs = token_lcs(&left.tokens, &right.tokens);
+        lcs.iter().map(|x| x.text()).collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn known_token_lcs() {
+        assert_eq!(lcs("", ""), "");
+        assert_eq!(lcs("a b c", ""), "");
+        assert_eq!(lcs("a b c", "d e f"), "");
+        assert_eq!(lcs("a b c", "a b c"), "a b c");
+        assert_eq!(lcs("A G G T A B", "G X T X A Y B"), "G T A B");
+        assert_eq!(lcs("a b c d e f", "f a c e"), "a c e");
+        assert_eq!(lcs("A B C B D A B", "B D C A B A").split(' ').count(), 4);
+    }
+
+    #[test]
+    fn alignment_lcs() {
+        let (left, right) = (Tokens::new("a b c d"), Tokens::new("a x c d e"));
+        let alignment = align(&AffineScoring::default(), &left.tokens, &right.tokens);
+        let lcs: Vec<&str> = alignment.lcs().iter().map(|x| x.text()).collect();
+        assert_eq!(lcs, ["a", "c", "d"]);
+        assert_eq!(token_lcs(&left.tokens, &right.tokens).len(), 3);
+    }
+}
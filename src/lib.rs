@@ -0,0 +1,199 @@
+pub mod alignment;
+pub mod anchors;
+pub mod blocks;
+pub mod cleanup;
+pub mod color_words;
+pub mod config;
+pub mod html;
+pub mod interner;
+pub mod lcs;
+pub mod lines;
+pub mod matrix;
+pub mod merge;
+mod runs;
+pub mod scoring;
+pub mod tokenizer;
+pub mod tree;
+pub mod types;
+pub mod width;
+
+pub use alignment::{align, Alignment, AlignmentOperation, OutputLine};
+pub use scoring::AffineScoring;
+pub use tokenizer::{Token, TokenParser, TokenType};
+pub use types::{AlignmentScoring, Token as TokenTrait};
+
+/// Tokens of a text, split into the ones that get aligned and the ones (whitespace and ignored
+/// tokens) that are interleaved back after the alignment.
+pub struct Tokens<'a> {
+    pub tokens: Vec<Token<'a, TokenType>>,
+    pub whitespace: Vec<Token<'a, TokenType>>,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self::partition(TokenParser::parse(text), |_| false)
+    }
+
+    /// Splits the tokens, leaving whitespace and tokens matching `ignore` out of the alignment.
+    pub fn partition(
+        tokens: impl Iterator<Item = Token<'a, TokenType>>,
+        ignore: impl Fn(&Token<'a, TokenType>) -> bool,
+    ) -> Self {
+        let (tokens, whitespace) = tokens
+            .map(|mut token| {
+                token.ignored = ignore(&token);
+                token
+            })
+            .partition(|x| !x.is_whitespace() && !x.is_ignored());
+        Tokens { tokens, whitespace }
+    }
+}
+
+/// Aligns two tokenized texts and interleaves the whitespace back, which is what the outputs
+/// show. The alignment borrows the tokens, so they are created separately with `Tokens::new`.
+/// When both sides have the same tokens, they are paired up directly without running `align`.
+pub fn diff<'t, 'a, S: AlignmentScoring<Token<'a, TokenType>>>(
+    left: &'t Tokens<'a>,
+    right: &'t Tokens<'a>,
+    scoring: &S,
+) -> Alignment<'t, Token<'a, TokenType>> {
+    let identical = left.tokens.len() == right.tokens.len()
+        && left
+            .tokens
+            .iter()
+            .zip(&right.tokens)
+            .all(|(left, right)| left.t == right.t && left.text() == right.text());
+    let alignment = if identical {
+        alignment::align_identical(scoring, &left.tokens, &right.tokens)
+    } else {
+        align(scoring, &left.tokens, &right.tokens)
+    };
+    alignment.interleave_tokens(&left.whitespace, &right.whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Texts of the changed tokens which aren't whitespace or ignored.
+    fn changes(left: &Tokens, right: &Tokens) -> Vec<String> {
+        diff(left, right, &AffineScoring::default())
+            .operations()
+            .iter()
+            .filter_map(|operation| match operation {
+                AlignmentOperation::Mutation { left, right } if left.text() == right.text() => None,
+                AlignmentOperation::Mutation { left, right } => {
+                    Some(format!("{}/{}", left.text(), right.text()))
+                }
+                AlignmentOperation::InsertLeft { left: token }
+                | AlignmentOperation::InsertRight { right: token } => (!token.is_whitespace()
+                    && !token.is_ignored())
+                .then(|| token.text().to_string()),
+            })
+            .collect()
+    }
+
+    /// Tokens of `text`, ignoring commas at the ends of lines.
+    fn without_trailing_commas(text: &str) -> Tokens<'_> {
+        Tokens::partition(TokenParser::parse(text).without_blocks(), |token| {
+            token.text() == "," && text[token.end..].trim_start_matches(' ').starts_with('\n')
+        })
+    }
+
+    #[test]
+    fn ignored_trailing_commas() {
+        let left = "[\n    first,\n    second\n]\n";
+        let right = "[\n    second,\n    first,\n]\n";
+        assert_eq!(
+            changes(&Tokens::new(left), &Tokens::new(right)),
+            ["second", ",", "second"]
+        );
+        assert_eq!(
+            changes(
+                &without_trailing_commas(left),
+                &without_trailing_commas(right)
+            ),
+            ["first", "first"]
+        );
+        let left = "f(\n    a,\n    b\n)\n";
+        let right = "f(\n    a,\n    b,\n    c\n)\n";
+        assert_eq!(
+            changes(
+                &without_trailing_commas(left),
+                &without_trailing_commas(right)
+            ),
+            ["c"]
+        );
+    }
+
+    /// Block tokens are aligned by nesting depth, so re-indenting a whole function pairs all its
+    /// blocks and only the changed number differs.
+    #[test]
+    fn reindented_blocks_are_paired() {
+        let left = Tokens::new(include_str!("../test/reindent_base.py"));
+        let right = Tokens::new(include_str!("../test/reindent_change.py"));
+        let alignment = diff(&left, &right, &AffineScoring::default());
+        let mut blocks = 0;
+        let mut changed = vec![];
+        for operation in alignment.operations() {
+            match operation {
+                AlignmentOperation::Mutation { left, right } => {
+                    if left.is_block_start() || left.is_block_end() {
+                        assert_eq!(left.t, right.t);
+                        blocks += 1;
+                    } else if left.text() != right.text() && !left.is_whitespace() {
+                        changed.push(format!("{}/{}", left.text(), right.text()));
+                    }
+                }
+                AlignmentOperation::InsertLeft { left: token }
+                | AlignmentOperation::InsertRight { right: token } => {
+                    assert!(token.is_whitespace(), "{:?}", token.text())
+                }
+            }
+        }
+        assert_eq!(blocks, 8);
+        assert_eq!(changed, ["2/3"]);
+    }
+
+    /// Scoring counting the scores it computes, which the DP does for every cell.
+    #[derive(Default)]
+    struct CountingScoring {
+        scoring: AffineScoring,
+        inserts: Cell<usize>,
+        mutations: Cell<usize>,
+    }
+
+    impl<'a> AlignmentScoring<Token<'a, TokenType>> for CountingScoring {
+        fn insert_score(&self, inserted: &Token<'a, TokenType>, previous_is_same: bool) -> f64 {
+            self.inserts.set(self.inserts.get() + 1);
+            self.scoring.insert_score(inserted, previous_is_same)
+        }
+
+        fn mutation_score(
+            &self,
+            left: &Token<'a, TokenType>,
+            right: &Token<'a, TokenType>,
+            previous_is_same: bool,
+        ) -> f64 {
+            self.mutations.set(self.mutations.get() + 1);
+            self.scoring.mutation_score(left, right, previous_is_same)
+        }
+    }
+
+    #[test]
+    fn identical_tokens_skip_the_dp() {
+        let text = include_str!("../test/base.rs");
+        let (left, right) = (Tokens::new(text), Tokens::new(text));
+        let scoring = CountingScoring::default();
+        let alignment = diff(&left, &right, &scoring);
+        assert_eq!(alignment.score(), 0.);
+        // Only the cost of each pair of tokens is computed, not a DP cell per pair of positions.
+        assert_eq!(scoring.inserts.get(), 0);
+        assert!(scoring.mutations.get() <= 2 * left.tokens.len());
+
+        let right = Tokens::new("fn main() {}");
+        diff(&left, &right, &scoring);
+        assert!(scoring.inserts.get() >= left.tokens.len() * right.tokens.len());
+    }
+}
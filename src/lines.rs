@@ -0,0 +1,423 @@
+use std::ops::Range;
+
+use colored::Colorize;
+
+use crate::alignment::{Alignment, AlignmentOperation, RenderOptions, Side, NO_NEWLINE};
+use crate::types::Token;
+
+/// Line level view of an alignment. Lines of both sides are reconstructed from the tokens, so the
+/// alignment needs to contain all tokens (including interleaved whitespace).
+#[derive(Debug)]
+pub struct LineDiff {
+    /// Lines of the left side, without the line endings.
+    pub left: Vec<String>,
+    /// Lines of the right side, without the line endings.
+    pub right: Vec<String>,
+    /// Pairs of identical lines (0-based), increasing on both sides.
+    pub matched: Vec<(usize, usize)>,
+    /// Leave out changes in which all lines are blank from the outputs.
+    pub ignore_blank_lines: bool,
+    /// The side which doesn't end with a line break while the other one does. Its last line is
+    /// never matched, as it differs from all lines of the other side in the line break.
+    pub missing_newline: Option<Side>,
+}
+
+/// Maximal run of lines which are not matched, on at least one side non-empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineChange {
+    pub left: Range<usize>,
+    pub right: Range<usize>,
+}
+
+fn split_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = text.split('\n').map(|x| x.to_string()).collect();
+    if lines.last().map(|x| x.is_empty()).unwrap_or(false) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Line without whitespace, except for a single space between words, so lines which differ only
+/// in whitespace (which is never aligned) become equal.
+fn normalize_whitespace(line: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(line.len());
+    for part in line.split_whitespace() {
+        let between_words = out.chars().next_back().map(is_word).unwrap_or(false)
+            && part.chars().next().map(is_word).unwrap_or(false);
+        if between_words {
+            out.push(' ');
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+impl<'a, T: Token> Alignment<'a, T> {
+    /// Script for `ed` which turns the left document into the right one, see
+    /// `LineDiff::ed_script`.
+    pub fn to_ed_script(&self) -> String {
+        self.line_diff().ed_script()
+    }
+
+    pub fn line_diff(&self) -> LineDiff {
+        self.line_diff_by(|left, right| left == right)
+    }
+
+    /// Same as `line_diff`, but lines which differ only in whitespace are matched.
+    pub fn line_diff_ignoring_whitespace(&self) -> LineDiff {
+        self.line_diff_by(|left, right| normalize_whitespace(left) == normalize_whitespace(right))
+    }
+
+    /// Same as `line_diff`, optionally matching lines which differ only in whitespace or in case.
+    pub fn line_diff_with(&self, ignore_whitespace: bool, ignore_case: bool) -> LineDiff {
+        let normalize = |line: &str| {
+            let line = if ignore_whitespace {
+                normalize_whitespace(line)
+            } else {
+                line.to_string()
+            };
+            if ignore_case {
+                line.to_lowercase()
+            } else {
+                line
+            }
+        };
+        self.line_diff_by(|left, right| normalize(left) == normalize(right))
+    }
+
+    fn line_diff_by(&self, equal: impl Fn(&str, &str) -> bool) -> LineDiff {
+        let mut left_text = String::new();
+        let mut right_text = String::new();
+        let (mut left_line, mut right_line) = (0, 0);
+        let mut candidates = vec![];
+        for operation in self.operations() {
+            if let AlignmentOperation::Mutation { left, right } = operation {
+                if !left.text().is_empty() && left.text() == right.text() {
+                    candidates.push((left_line, right_line));
+                }
+            }
+            if let Some(left) = operation.left() {
+                left_text.push_str(left.text());
+                left_line += left.text().matches('\n').count();
+            }
+            if let Some(right) = operation.right() {
+                right_text.push_str(right.text());
+                right_line += right.text().matches('\n').count();
+            }
+        }
+        let left = split_lines(&left_text);
+        let right = split_lines(&right_text);
+        let mut anchors: Vec<(usize, usize)> = vec![];
+        for (l, r) in candidates {
+            let increasing = anchors
+                .last()
+                .map(|&(pl, pr)| l > pl && r > pr)
+                .unwrap_or(true);
+            if increasing && l < left.len() && r < right.len() && equal(&left[l], &right[r]) {
+                anchors.push((l, r));
+            }
+        }
+        // Lines without matched tokens (e.g. blank lines) are matched when equal at the edges
+        // of gaps between anchors.
+        let mut matched = vec![];
+        let mut previous = (0, 0);
+        for &(l, r) in anchors
+            .iter()
+            .chain(std::iter::once(&(left.len(), right.len())))
+        {
+            let (mut l_start, mut r_start) = previous;
+            let (mut l_end, mut r_end) = (l, r);
+            while l_start < l_end && r_start < r_end && equal(&left[l_start], &right[r_start]) {
+                matched.push((l_start, r_start));
+                l_start += 1;
+                r_start += 1;
+            }
+            let mut tail = vec![];
+            while l_start < l_end && r_start < r_end && equal(&left[l_end - 1], &right[r_end - 1]) {
+                l_end -= 1;
+                r_end -= 1;
+                tail.push((l_end, r_end));
+            }
+            matched.extend(tail.into_iter().rev());
+            if l < left.len() && r < right.len() {
+                matched.push((l, r));
+            }
+            previous = (l + 1, r + 1);
+        }
+        let missing_newline = self.missing_newline();
+        match missing_newline {
+            Some(Side::Left) => matched.retain(|&(l, _)| l + 1 != left.len()),
+            Some(Side::Right) => matched.retain(|&(_, r)| r + 1 != right.len()),
+            None => {}
+        }
+        LineDiff {
+            left,
+            right,
+            matched,
+            ignore_blank_lines: false,
+            missing_newline,
+        }
+    }
+}
+
+impl LineDiff {
+    pub fn changes(&self) -> Vec<LineChange> {
+        let mut changes = vec![];
+        let (mut left_start, mut right_start) = (0, 0);
+        for &(l, r) in self
+            .matched
+            .iter()
+            .chain(std::iter::once(&(self.left.len(), self.right.len())))
+        {
+            if l > left_start || r > right_start {
+                changes.push(LineChange {
+                    left: left_start..l,
+                    right: right_start..r,
+                });
+            }
+            left_start = l + 1;
+            right_start = r + 1;
+        }
+        changes
+    }
+
+    /// Whether `diff` would note after the line (0-based) of `side` that it's the last one and
+    /// doesn't end with a line break.
+    fn lacks_newline(&self, side: Side, line: usize) -> bool {
+        let lines = match side {
+            Side::Left => &self.left,
+            Side::Right => &self.right,
+        };
+        self.missing_newline == Some(side) && line + 1 == lines.len()
+    }
+
+    /// Whether the change is left out of the outputs, see `ignore_blank_lines`.
+    fn is_ignored(&self, change: &LineChange) -> bool {
+        let blank = |line: &String| line.trim().is_empty();
+        self.ignore_blank_lines
+            && self.left[change.left.clone()].iter().all(blank)
+            && self.right[change.right.clone()].iter().all(blank)
+    }
+
+    /// Output in the format of `diff --normal`.
+    pub fn normal(&self, options: &RenderOptions) -> String {
+        // 1-based range, or the line after which the change happens for empty ranges.
+        fn range(range: &Range<usize>) -> String {
+            if range.len() <= 1 {
+                format!("{}", range.start + range.len())
+            } else {
+                format!("{},{}", range.start + 1, range.end)
+            }
+        }
+        let mut out = String::new();
+        for change in self.changes() {
+            if self.is_ignored(&change) {
+                continue;
+            }
+            let command = if change.right.is_empty() {
+                'd'
+            } else if change.left.is_empty() {
+                'a'
+            } else {
+                'c'
+            };
+            out.push_str(&format!(
+                "{}{}{}\n",
+                range(&change.left),
+                command,
+                range(&change.right)
+            ));
+            for index in change.left.clone() {
+                out.push_str(&format!("{}\n", options.line("<", &self.left[index])));
+                if self.lacks_newline(Side::Left, index) {
+                    out.push_str(&format!("{}\n", NO_NEWLINE));
+                }
+            }
+            if command == 'c' {
+                out.push_str("---\n");
+            }
+            for index in change.right.clone() {
+                out.push_str(&format!("{}\n", options.line(">", &self.right[index])));
+                if self.lacks_newline(Side::Right, index) {
+                    out.push_str(&format!("{}\n", NO_NEWLINE));
+                }
+            }
+        }
+        out
+    }
+
+    /// Output in the unified format of `diff -u`, with `context` unchanged lines around each
+    /// change. Changes closer than twice the context are in the same hunk. Empty when there are
+    /// no changed lines.
+    pub fn unified(
+        &self,
+        left_name: &str,
+        right_name: &str,
+        context: usize,
+        options: &RenderOptions,
+    ) -> String {
+        // 1-based start and length, where empty ranges start at the line before them.
+        fn range(start: usize, len: usize) -> String {
+            match len {
+                0 => format!("{},0", start),
+                1 => format!("{}", start + 1),
+                _ => format!("{},{}", start + 1, len),
+            }
+        }
+        let changes = self.changes();
+        let mut hunks: Vec<&[LineChange]> = vec![];
+        let mut hunk_start = 0;
+        for index in 1..=changes.len() {
+            // Lines between changes are matched, so the gap is the same on both sides.
+            let separate = changes
+                .get(index)
+                .map(|next| next.left.start - changes[index - 1].left.end > 2 * context)
+                .unwrap_or(true);
+            if separate {
+                hunks.push(&changes[hunk_start..index]);
+                hunk_start = index;
+            }
+        }
+        // Ignored changes are shown only in hunks with other changes.
+        hunks.retain(|hunk| !hunk.iter().all(|change| self.is_ignored(change)));
+        if hunks.is_empty() {
+            return String::new();
+        }
+        let mut out = format!("--- {}\n+++ {}\n", left_name, right_name);
+        for hunk in hunks {
+            let (first, last) = (&hunk[0], &hunk[hunk.len() - 1]);
+            let before = context.min(first.left.start);
+            let after = context.min(self.left.len() - last.left.end);
+            let left = first.left.start - before..last.left.end + after;
+            let right = first.right.start - before..last.right.end + after;
+            out.push_str(&format!(
+                "@@ -{} +{} @@\n",
+                range(left.start, left.len()),
+                range(right.start, right.len())
+            ));
+            let mut line = left.start;
+            for change in hunk {
+                for text in &self.left[line..change.left.start] {
+                    out.push_str(&format!(" {}\n", options.text(text)));
+                }
+                for index in change.left.clone() {
+                    out.push_str(&format!("-{}\n", options.text(&self.left[index])));
+                    if self.lacks_newline(Side::Left, index) {
+                        out.push_str(&format!("{}\n", NO_NEWLINE));
+                    }
+                }
+                for index in change.right.clone() {
+                    out.push_str(&format!("+{}\n", options.text(&self.right[index])));
+                    if self.lacks_newline(Side::Right, index) {
+                        out.push_str(&format!("{}\n", NO_NEWLINE));
+                    }
+                }
+                line = change.left.end;
+            }
+            for text in &self.left[line..left.end] {
+                out.push_str(&format!(" {}\n", options.text(text)));
+            }
+        }
+        out
+    }
+
+    /// Script for `ed` in the format of `diff -e`, which turns the left side into the right one.
+    /// Changes are listed from the end, so the line numbers of the remaining ones stay valid.
+    pub fn ed_script(&self) -> String {
+        let mut out = String::new();
+        for change in self.changes().into_iter().rev() {
+            if self.is_ignored(&change) {
+                continue;
+            }
+            let left = &change.left;
+            let (command, range) = if left.is_empty() {
+                ('a', format!("{}", left.start))
+            } else if left.len() == 1 {
+                (
+                    if change.right.is_empty() { 'd' } else { 'c' },
+                    format!("{}", left.end),
+                )
+            } else {
+                (
+                    if change.right.is_empty() { 'd' } else { 'c' },
+                    format!("{},{}", left.start + 1, left.end),
+                )
+            };
+            out.push_str(&format!("{}{}\n", range, command));
+            if command == 'd' {
+                continue;
+            }
+            for line in &self.right[change.right] {
+                if line == "." {
+                    // A lone `.` ends the input, so it's written as `..` and fixed up afterwards,
+                    // continuing the input after it.
+                    out.push_str("..\n.\ns/.//\na\n");
+                } else {
+                    out.push_str(&format!("{}\n", line));
+                }
+            }
+            out.push_str(".\n");
+        }
+        out
+    }
+
+    /// Number of inserted and deleted lines.
+    pub fn counts(&self) -> (usize, usize) {
+        self.changes()
+            .iter()
+            .filter(|change| !self.is_ignored(change))
+            .fold((0, 0), |(insertions, deletions), change| {
+                (
+                    insertions + change.right.len(),
+                    deletions + change.left.len(),
+                )
+            })
+    }
+
+    /// Summary in the format of `git diff --stat`, with the bar scaled to fit into `width`
+    /// columns. Empty when there are no changed lines.
+    pub fn stat(&self, name: &str, width: usize) -> String {
+        let (insertions, deletions) = self.counts();
+        let total = insertions + deletions;
+        if total == 0 {
+            return String::new();
+        }
+        let count = total.to_string();
+        // " name | count " takes the rest of the line.
+        let bar_width = width
+            .saturating_sub(name.chars().count() + count.len() + 5)
+            .max(1);
+        let (plus, minus) = if total <= bar_width {
+            (insertions, deletions)
+        } else {
+            // Scale, keeping at least one character for non-zero counts.
+            let scale = |x: usize| {
+                if x == 0 {
+                    0
+                } else {
+                    (x * bar_width / total).max(1)
+                }
+            };
+            let plus = scale(insertions);
+            (plus, bar_width.saturating_sub(plus).min(scale(deletions)))
+        };
+        let plural =
+            |x: usize, word: &str| format!("{} {}{}", x, word, if x == 1 { "" } else { "s" });
+        let mut summary = " 1 file changed".to_string();
+        if insertions > 0 {
+            summary.push_str(&format!(", {}(+)", plural(insertions, "insertion")));
+        }
+        if deletions > 0 {
+            summary.push_str(&format!(", {}(-)", plural(deletions, "deletion")));
+        }
+        format!(
+            " {} | {} {}{}\n{}\n",
+            name,
+            count,
+            "+".repeat(plus).green(),
+            "-".repeat(minus).red(),
+            summary
+        )
+    }
+}
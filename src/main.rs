@@ -1,99 +1,1356 @@
-mod alignment;
-mod tokenizer;
-mod types;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
-use std::path::PathBuf;
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use regex::Regex;
 
-use clap::Parser;
-
-use alignment::align;
-use tokenizer::{Token, TokenParser, TokenType};
-use types::{AlignmentScoring, Token as _};
+use platypus_diff::alignment::{
+    align_banded_with_stats, align_linear_with_stats, align_low_memory_with_stats, align_segments,
+    align_with_stats, AlignmentStats, ChangeKind, Emphasis, Markers, RenderOptions, TAB_WIDTH,
+};
+use platypus_diff::interner::Interner;
+use platypus_diff::merge::{self as merge3, Version};
+use platypus_diff::scoring::{
+    parse_penalty, GapCost, InsertPenalties, DEFAULT_BLOCK_END_PENALTY, DEFAULT_EXTEND_INSERT,
+    DEFAULT_MISMATCHED_TEXT_PENALTY, DEFAULT_MISMATCHED_TYPE_PENALTY, DEFAULT_START_INSERT,
+};
+use platypus_diff::tokenizer::{CommentStyle, Granularity, DEFAULT_BLOCK_CHARS};
+use platypus_diff::{
+    alignment, anchors, blocks, config, lcs, matrix, tree, width, AffineScoring, Alignment,
+    AlignmentOperation, Token, TokenParser, TokenTrait as _, TokenType, Tokens,
+};
 
 // TODO: Insert BlockStart/BlockEnd for whitespace
 // TODO: Eventually better parsing -- i.e. add BlockStart/BlockEnd for non-whitesace things
-// TODO: Add line and col numbers to tokens
-
-struct AffineScoring {
-    pub start_insert: f64,
-    pub extend_insert: f64,
-    pub block_end_insert_penalty: f64,
-    pub mismatched_type_penalty: f64,
-    pub mismatched_text_penalty: f64,
-    pub mismatched_case_penalty: f64,
-}
-
-impl<'a> AlignmentScoring<Token<'a, TokenType>> for AffineScoring {
-    fn insert_score(&self, inserted: &Token<'a, TokenType>, previous_is_same: bool) -> f64 {
-        let add = match inserted.t {
-            TokenType::BlockEnd(_indent) => self.block_end_insert_penalty,
-            _ => 0.0,
-        };
-        if previous_is_same {
-            self.extend_insert + add
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MatrixFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Color {
+    /// Color the output even when it's not a terminal, e.g. for `less -R`.
+    Always,
+    /// Color the output when it's a terminal and NO_COLOR is not set.
+    Auto,
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    /// Colored word diff of the right document.
+    Pretty,
+    /// Same as `diff --normal`.
+    Normal,
+    /// Same as `diff -u`.
+    Unified,
+    /// Script for `ed` which turns the left file into the right one, same as `diff -e`.
+    Ed,
+    /// JSON array of the alignment operations, with all tokens including whitespace.
+    Json,
+    /// `<pre>` block of the right document with deleted and inserted text in styled spans.
+    Html,
+    /// Right document as running text with deleted and inserted words colored, like
+    /// `git diff --color-words`.
+    ColorWords,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Algorithm {
+    /// Lowest cost alignment under the insert and mutation penalties.
+    Affine,
+    /// Myers' shortest edit script, matching only equal tokens. Much faster for similar inputs.
+    Myers,
+    /// Match lines which are unique and identical on both sides first (as in patience diff), and
+    /// align the parts between them like `affine`.
+    Patience,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum LineAnchors {
+    /// Use anchors only for large inputs.
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Cleanup {
+    None,
+    /// Merge short equalities between changes and move changes to token type boundaries.
+    Semantic,
+    /// Move inserted and deleted runs to token type boundaries where this keeps the cost.
+    Boundaries,
+}
+
+/// Lines of context in the unified format, unless `--context` is given.
+const UNIFIED_CONTEXT: usize = 3;
+
+/// Inputs with more token pairs than this get line anchors with `--line-anchors auto`.
+const LINE_ANCHORS_AUTO_CELLS: usize = 1_000_000;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[arg(short, long)]
+    debug: bool,
+    /// Print statistics about the alignment (time, evaluated cells) to stderr.
+    #[arg(short, long)]
+    verbose: bool,
+    /// When to color the output.
+    #[arg(long, value_enum, default_value_t = Color::Auto, value_name = "WHEN")]
+    color: Color,
+    /// Same as `--color never`.
+    #[arg(long, conflicts_with = "color")]
+    no_color: bool,
+    /// Write the diff to this file instead of stdout, without colors unless `--color always` is
+    /// given. Errors and `--verbose` statistics still go to stderr.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Print nothing, only exit with 1 when the inputs differ and 0 when they don't.
+    #[arg(short, long)]
+    quiet: bool,
+    /// Print only whether the inputs differ, like `diff -q`. Texts are compared as they are
+    /// unless options like `--ignore-whitespace` make different texts equal, which then need to be
+    /// aligned.
+    #[arg(long, visible_alias = "summary-only", conflicts_with = "quiet")]
+    brief: bool,
+    #[arg(long, value_enum, default_value_t = Format::Pretty)]
+    format: Format,
+    /// Show only this many unchanged lines around changes. In the pretty output the other
+    /// unchanged lines are folded into a single marker, the unified format shows 3 lines by
+    /// default.
+    #[arg(
+        short = 'U',
+        long,
+        visible_alias = "unified",
+        alias = "fold",
+        value_name = "LINES"
+    )]
+    context: Option<usize>,
+    /// Show entirely inserted or deleted indented blocks as a single line.
+    #[arg(long)]
+    collapse_block_changes: bool,
+    /// Show deleted text which is inserted elsewhere unchanged (ignoring whitespace) in cyan.
+    #[arg(long)]
+    detect_moves: bool,
+    /// Highlight only the characters that differ within changed words.
+    #[arg(long, visible_alias = "char-level")]
+    refine_words: bool,
+    /// Underline words which differ only in case instead of showing them as changed.
+    #[arg(long)]
+    show_case_changes: bool,
+    /// Ignore changes in whitespace, including indentation and lines which differ only in
+    /// whitespace.
+    #[arg(short = 'w', long)]
+    ignore_whitespace: bool,
+    /// Ignore changes in case. Comparison is case sensitive by default.
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+    /// Ignore changes which only insert or delete blank lines in the line based outputs. The
+    /// pretty output never shows them, as whitespace is not aligned.
+    #[arg(short = 'B', long)]
+    ignore_blank_lines: bool,
+    /// Treat strings in `"` or `'` quotes as single tokens.
+    #[arg(long)]
+    string_literals: bool,
+    /// Unit of the diff.
+    #[arg(long, value_enum, default_value_t = Granularity::Word)]
+    granularity: Granularity,
+    /// Extra characters which are part of words, e.g. `-` to keep kebab-case names together.
+    #[arg(
+        long,
+        default_value = "",
+        value_name = "CHARS",
+        allow_hyphen_values = true
+    )]
+    word_chars: String,
+    /// Characters which are tokens of their own instead of being part of runs of special
+    /// characters, e.g. `<>` for templates.
+    #[arg(
+        long,
+        default_value = DEFAULT_BLOCK_CHARS,
+        value_name = "CHARS",
+        allow_hyphen_values = true
+    )]
+    block_chars: String,
+    /// Treat brackets as ordinary special characters, same as `--block-chars ''`.
+    #[arg(long, conflicts_with = "block_chars")]
+    no_block_chars: bool,
+    /// Treat comments of this syntax as single tokens, which are never matched with code.
+    #[arg(long, value_enum, default_value_t = CommentStyle::None)]
+    comment_style: CommentStyle,
+    /// Leave tokens with this text out of the alignment and show them as unchanged. Can be
+    /// repeated.
+    #[arg(long)]
+    ignore_token: Vec<String>,
+    /// Post-process the alignment to make the diff easier to read.
+    #[arg(long, value_enum, default_value_t = Cleanup::None)]
+    cleanup: Cleanup,
+    /// Plain diff without structure: no block tokens and whole line changes in the output.
+    #[arg(long, conflicts_with_all = [
+        "format", "collapse_block_changes", "detect_moves", "refine_words", "show_case_changes",
+        "emphasize"
+    ])]
+    plain: bool,
+    /// Print only the location of the first differing token on each side, without aligning.
+    #[arg(long)]
+    first_diff: bool,
+    /// Print only a summary of changed lines, like `git diff --stat`.
+    #[arg(long)]
+    stat: bool,
+    /// Show the documents in two columns next to each other.
+    #[arg(short = 'y', long, conflicts_with_all = ["format", "plain", "stat"])]
+    side_by_side: bool,
+    /// After the diff, print the numbers of inserted, deleted and mutated tokens as `+N -M ~K`.
+    #[arg(long)]
+    stats: bool,
+    /// Width of the output in columns. Defaults to $COLUMNS, then to the width of the terminal.
+    #[arg(long)]
+    width: Option<usize>,
+    /// Show runs of blank lines as a single blank line, like `cat -s`.
+    #[arg(long)]
+    squeeze_blank: bool,
+    /// Show only added or only removed text of changed lines, without unchanged lines.
+    #[arg(long, value_enum)]
+    only: Option<ChangeKind>,
+    /// Which kind of changes to highlight.
+    #[arg(long, value_enum, default_value_t = Emphasis::Text)]
+    emphasize: Emphasis,
+    /// Width of a tab when comparing indentation levels.
+    #[arg(long, default_value_t = 8, value_name = "WIDTH")]
+    indent_tab_width: usize,
+    /// Expand tabs to spaces in the output.
+    #[arg(long)]
+    expand_tabs: bool,
+    /// Expand tabs in the output to tab stops this many columns apart, so columns line up when
+    /// one version is indented with tabs and the other with spaces. Implies `--expand-tabs`.
+    /// Defaults to 8.
+    #[arg(long, value_name = "WIDTH", value_parser = parse_tab_width)]
+    tab_width: Option<usize>,
+    /// Put a tab instead of a space between the change marker and the text.
+    #[arg(long)]
+    initial_tab: bool,
+    /// Prefix of unchanged lines.
+    #[arg(long, default_value = " ", value_parser = parse_marker)]
+    marker_same: String,
+    /// Prefix of deleted lines.
+    #[arg(long, default_value = "-", value_parser = parse_marker)]
+    marker_del: String,
+    /// Prefix of added lines.
+    #[arg(long, default_value = "+", value_parser = parse_marker)]
+    marker_add: String,
+    /// Wrap lines with right-to-left text (Arabic, Hebrew) in bidi isolates, so terminals with
+    /// bidi support keep the change marker in front of the line.
+    #[arg(long)]
+    bidi_isolate: bool,
+    /// Print pairwise similarity (0..1) of all given files instead of a diff.
+    #[arg(long, num_args = 2.., conflicts_with_all = ["left", "right"])]
+    matrix: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t = MatrixFormat::Csv)]
+    matrix_format: MatrixFormat,
+    /// Merge LEFT and RIGHT, which were both changed from this version, and print the result with
+    /// git-style conflict markers around conflicting changes. Exits with 1 when there are
+    /// conflicts.
+    #[arg(long, conflicts_with_all = ["matrix", "split_on", "left_text", "right_text"])]
+    base: Option<PathBuf>,
+    /// Split both inputs into records at this string and diff the records pairwise, so content is
+    /// never matched across records.
+    #[arg(long)]
+    record_separator: Option<String>,
+    /// Pair top-level blocks (e.g. functions) of both sides by similarity and diff only paired
+    /// blocks against each other. Unpaired blocks are shown as entirely removed or added.
+    #[arg(long, conflicts_with = "record_separator")]
+    block_align: bool,
+    /// Split inputs at lines that are identical and unique on both sides before aligning.
+    #[arg(long, value_enum, default_value_t = LineAnchors::Auto)]
+    line_anchors: LineAnchors,
+    /// When one input is empty, show the other one as the displayed document, so its content is
+    /// readable instead of struck through.
+    #[arg(long)]
+    swap_on_empty: bool,
+    /// Alignment algorithm. Memory and band options don't apply to `myers`.
+    #[arg(long, value_enum, default_value_t = Algorithm::Affine)]
+    algorithm: Algorithm,
+    /// Compute the same alignment with much less memory, at about twice the time.
+    #[arg(long)]
+    low_memory: bool,
+    /// Compute the same alignment with memory linear in the input size, at about twice the time.
+    #[arg(long, conflicts_with = "low_memory")]
+    linear_memory: bool,
+    /// Only compute cells within this many diagonals of the shortest path, doubling it when the
+    /// alignment reaches its edge or a path leaving it could be cheaper. Much faster for inputs
+    /// with few changes.
+    #[arg(long, value_name = "DIAGONALS", conflicts_with_all = ["low_memory", "linear_memory"])]
+    band: Option<usize>,
+    /// Compute the same alignment on multiple threads.
+    #[cfg(feature = "parallel")]
+    #[arg(long, conflicts_with_all = ["low_memory", "linear_memory", "band"])]
+    parallel: bool,
+    /// Config file with `key = value` lines. Supported keys are `insert.<type>.start` and
+    /// `insert.<type>.extend`, with type `whitespace`, `special`, `word`, `string`, `number`,
+    /// `comment`, `block_start` or `block_end`.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Cost of starting an insert or delete.
+    #[arg(
+        long,
+        default_value_t = DEFAULT_START_INSERT,
+        value_parser = parse_penalty,
+        allow_negative_numbers = true
+    )]
+    start_insert: f64,
+    /// Cost of each further inserted or deleted token.
+    #[arg(
+        long,
+        default_value_t = DEFAULT_EXTEND_INSERT,
+        value_parser = parse_penalty,
+        allow_negative_numbers = true
+    )]
+    extend_insert: f64,
+    /// Extra cost of inserting or deleting the end of a block.
+    #[arg(
+        long,
+        default_value_t = DEFAULT_BLOCK_END_PENALTY,
+        value_parser = parse_penalty,
+        allow_negative_numbers = true
+    )]
+    block_end_penalty: f64,
+    /// Cost of aligning tokens of different types.
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MISMATCHED_TYPE_PENALTY,
+        value_parser = parse_penalty,
+        allow_negative_numbers = true
+    )]
+    mismatch_type_penalty: f64,
+    /// Cost of aligning tokens with different text.
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MISMATCHED_TEXT_PENALTY,
+        value_parser = parse_penalty,
+        allow_negative_numbers = true
+    )]
+    mismatch_text_penalty: f64,
+    /// How the cost of inserted or deleted runs grows with their length.
+    #[arg(long, value_enum, default_value_t = GapCost::Affine)]
+    gap_cost: GapCost,
+    /// Bonus for a match following another match, which reduces fragmentation of the diff.
+    #[arg(
+        long,
+        default_value_t = 0.,
+        value_parser = parse_penalty,
+        allow_negative_numbers = true
+    )]
+    match_extend_bonus: f64,
+    /// Align numbers which differ by at most this much as if they were equal, e.g. `1.0` and
+    /// `1.00`. They are still shown as changed.
+    #[arg(long, value_name = "EPS", value_parser = parse_tolerance)]
+    numeric_tolerance: Option<f64>,
+    /// Treat aligned tokens which both match this regex as unchanged, e.g. `^[0-9a-f]{8,}$` for
+    /// generated ids. Tokens are words, numbers and single special characters, so the regex
+    /// matches parts of a timestamp rather than the whole. This runs the regex for most pairs of
+    /// tokens while aligning, which makes it take about twice as long.
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    ignore_matching: Option<Regex>,
+    /// Score changed words by their edit distance, so similar words (`color` and `colour`) are
+    /// paired before unrelated ones. Makes aligning slower.
+    #[arg(long)]
+    fuzzy_words: bool,
+    /// Refuse to align inputs whose estimated memory use exceeds this many MiB.
+    #[arg(long, default_value_t = 4096)]
+    memory_budget: usize,
+    /// Refuse to align inputs with more than this many tokens (excluding whitespace), as aligning
+    /// takes time proportional to the product of their lengths. 0 means no limit.
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    max_tokens: usize,
+    /// Replace invalid UTF-8 in inputs with U+FFFD instead of failing.
+    #[arg(long)]
+    lossy_utf8: bool,
+    /// Convert CRLF line endings to LF, so inputs differing only in line endings are equal.
+    #[arg(long)]
+    strip_cr: bool,
+    /// Read both versions from the single given file, separated by a line equal to this marker.
+    #[arg(long, conflicts_with_all = ["right", "left_text", "right_text"])]
+    split_on: Option<String>,
+    /// Use this text as the left version instead of reading a file.
+    #[arg(long, conflicts_with = "matrix")]
+    left_text: Option<String>,
+    /// Use this text as the right version instead of reading a file.
+    #[arg(long, conflicts_with = "matrix")]
+    right_text: Option<String>,
+    /// Skip files and directories whose name matches this glob (`*` and `?`) when comparing
+    /// directories. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Path of the left version, or of the right one when only `--left-text` is given. When both
+    /// paths are directories, files with the same relative path are compared.
+    #[arg(required_unless_present_any = ["matrix", "left_text", "right_text"])]
+    left: Option<PathBuf>,
+    #[arg(required_unless_present_any = ["matrix", "split_on", "left_text", "right_text"])]
+    right: Option<PathBuf>,
+}
+
+fn parse_marker(marker: &str) -> Result<String, String> {
+    if marker.contains(['\n', '\r']) {
+        Err("markers can't contain line breaks".to_string())
+    } else {
+        Ok(marker.to_string())
+    }
+}
+
+fn parse_tab_width(width: &str) -> Result<usize, String> {
+    match width.parse::<usize>() {
+        Ok(0) => Err("tab width must be positive".to_string()),
+        Ok(width) => Ok(width),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_tolerance(value: &str) -> Result<f64, String> {
+    parse_penalty(value).map_err(|_| format!("tolerance {:?} is not a non-negative number", value))
+}
+
+/// Insert penalties from the command line, with the ones from `--config` applied over them.
+fn config_insert_penalties(cli: &Cli) -> InsertPenalties {
+    let mut penalties =
+        InsertPenalties::new(cli.start_insert, cli.extend_insert, cli.block_end_penalty);
+    if let Some(path) = &cli.config {
+        let result =
+            config::parse(&read_text(path, false)).and_then(|config| penalties.configure(&config));
+        if let Err(err) = result {
+            eprintln!("error: {}: {}", path.display(), err);
+            std::process::exit(2);
+        }
+    }
+    penalties
+}
+
+fn scoring(cli: &Cli) -> AffineScoring {
+    AffineScoring::default()
+        .with_insert_penalties(config_insert_penalties(cli))
+        .with_mismatched_type_penalty(cli.mismatch_type_penalty)
+        .with_mismatched_text_penalty(cli.mismatch_text_penalty)
+        .with_ignore_case(cli.ignore_case)
+        .with_gap_cost(cli.gap_cost)
+        .with_match_extend_bonus(cli.match_extend_bonus)
+        .with_numeric_tolerance(cli.numeric_tolerance)
+        .with_ignore_matching(cli.ignore_matching.clone())
+        .with_fuzzy_words(cli.fuzzy_words)
+}
+
+/// Path `-` stands for the standard input.
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Contents of an input before decoding, with the name used in messages.
+struct Input {
+    name: String,
+    bytes: Vec<u8>,
+}
+
+impl Input {
+    /// Like `diff`, inputs with NUL bytes are treated as binary.
+    fn is_binary(&self) -> bool {
+        self.bytes.contains(&0)
+    }
+
+    fn decode(self, lossy: bool) -> Result<String, String> {
+        if lossy {
+            return Ok(String::from_utf8_lossy(&self.bytes).into_owned());
+        }
+        String::from_utf8(self.bytes).map_err(|err| {
+            format!(
+                "{}: not valid UTF-8 (invalid byte at offset {}); re-encode it as UTF-8 or use \
+                 --lossy-utf8",
+                self.name,
+                err.utf8_error().valid_up_to()
+            )
+        })
+    }
+}
+
+fn read_input(path: &PathBuf) -> Result<Input, String> {
+    let bytes = if is_stdin(path) {
+        let mut bytes = vec![];
+        std::io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+    } else {
+        std::fs::read(path)
+    };
+    Ok(Input {
+        name: path.display().to_string(),
+        bytes: bytes.map_err(|err| format!("{}: {}", path.display(), err))?,
+    })
+}
+
+/// Reads the file (or the standard input for `-`) as text, or exits with an error. Invalid UTF-8
+/// is an error unless `lossy` is set.
+fn read_text(path: &PathBuf, lossy: bool) -> String {
+    read_input(path)
+        .and_then(|input| input.decode(lossy))
+        .unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        })
+}
+
+/// Both versions, from `--left-text` and `--right-text` or read from the paths. Paths are used
+/// in order for the sides without a text.
+fn read_inputs(cli: &Cli) -> Result<(Input, Input), String> {
+    if cli
+        .left
+        .iter()
+        .chain(cli.right.iter())
+        .filter(|x| is_stdin(x))
+        .count()
+        > 1
+    {
+        return Err("only one input can be read from the standard input".to_string());
+    }
+    let mut paths = cli.left.iter().chain(cli.right.iter());
+    let mut side = |text: &Option<String>, name: &str| match text {
+        Some(text) => Ok(Input {
+            name: format!("--{}-text", name),
+            bytes: text.clone().into_bytes(),
+        }),
+        None => paths
+            .next()
+            .ok_or_else(|| format!("missing the {} path or --{}-text", name, name))
+            .and_then(read_input),
+    };
+    let left = side(&cli.left_text, "left")?;
+    let right = side(&cli.right_text, "right")?;
+    if paths.next().is_some() {
+        return Err("a path and --left-text or --right-text given for the same side".to_string());
+    }
+    Ok((left, right))
+}
+
+fn tokenize<'a>(cli: &Cli, text: &'a str) -> TokenParser<'a> {
+    let mut parser = TokenParser::parse(text).with_tab_width(cli.indent_tab_width);
+    // Without block tokens, indentation is just whitespace, which is not aligned.
+    if cli.plain || cli.ignore_whitespace {
+        parser = parser.without_blocks();
+    }
+    if cli.string_literals {
+        parser = parser.with_string_literals();
+    }
+    parser = parser
+        .with_comments(cli.comment_style)
+        .with_word_chars(&cli.word_chars)
+        .with_block_chars(if cli.no_block_chars {
+            ""
         } else {
-            self.start_insert + add
+            &cli.block_chars
+        })
+        .with_granularity(match cli.granularity {
+            // Sentences only split the word diff, see `sentence_splits`.
+            Granularity::Sentence => Granularity::Word,
+            granularity => granularity,
+        });
+    parser
+}
+
+/// Splits for `align_segments` which pair sentences by the words they share, so the word diff is
+/// done within paired sentences and unpaired ones are entirely removed or added.
+fn sentence_splits(
+    cli: &Cli,
+    scoring: &AffineScoring,
+    (left_text, left_tokens): (&str, &[Token<'_, TokenType>]),
+    (right_text, right_tokens): (&str, &[Token<'_, TokenType>]),
+) -> Vec<(usize, usize)> {
+    let sentences = |text| -> Vec<_> {
+        tokenize(cli, text)
+            .with_granularity(Granularity::Sentence)
+            .filter(|x| !x.is_whitespace())
+            .collect()
+    };
+    let (left_sentences, right_sentences) = (sentences(left_text), sentences(right_text));
+    let units = |sentence: &Token<'_, TokenType>, tokens: &[Token<'_, TokenType>]| {
+        token_index(tokens, sentence.start())..token_index(tokens, sentence.end)
+    };
+    let mut splits = vec![];
+    let mut right_end = 0;
+    for operation in alignment::align(scoring, &left_sentences, &right_sentences).operations() {
+        let AlignmentOperation::Mutation { left, right } = operation else {
+            continue;
+        };
+        let (l, r) = (units(left, left_tokens), units(right, right_tokens));
+        // Unpaired sentences before this pair: first the removed ones, then the added ones.
+        splits.push((l.start, right_end));
+        splits.push((l.start, r.start));
+        splits.push((l.end, r.end));
+        right_end = r.end;
+    }
+    splits.push((left_tokens.len(), right_end));
+    splits
+}
+
+fn print_matrix(
+    cli: &Cli,
+    out: &mut dyn Write,
+    files: &[PathBuf],
+    format: MatrixFormat,
+) -> io::Result<()> {
+    let texts: Vec<String> = files
+        .iter()
+        .map(|file| read_text(file, cli.lossy_utf8))
+        .collect();
+    let mut interner = Interner::default();
+    let documents: Vec<Vec<_>> = texts
+        .iter()
+        .map(|text| {
+            let mut tokens: Vec<_> = tokenize(cli, text).filter(|x| !x.is_whitespace()).collect();
+            interner.intern_tokens(&mut tokens);
+            tokens
+        })
+        .collect();
+    for (file, tokens) in files.iter().zip(&documents) {
+        check_max_tokens(cli, &file.display().to_string(), tokens.len());
+    }
+    let matrix = matrix::similarity_matrix(&scoring(cli), &documents);
+    match format {
+        MatrixFormat::Csv => write!(out, "{}", matrix::to_csv(files, &matrix)),
+        MatrixFormat::Json => write!(out, "{}", matrix::to_json(files, &matrix)),
+    }
+}
+
+/// Exits with an error when the input has more tokens than `--max-tokens` allows.
+fn check_max_tokens(cli: &Cli, name: &str, tokens: usize) {
+    if cli.max_tokens != 0 && tokens > cli.max_tokens {
+        eprintln!(
+            "error: {}: {} tokens exceed the limit of {}; use --granularity line for large inputs, \
+             or raise --max-tokens",
+            name, tokens, cli.max_tokens
+        );
+        std::process::exit(2);
+    }
+}
+
+/// Index of the first token starting at or after `offset`.
+fn token_index(tokens: &[Token<'_, TokenType>], offset: usize) -> usize {
+    tokens.partition_point(|token| token.start() < offset)
+}
+
+/// Indices of the first token of every record except the first one.
+fn record_starts(text: &str, tokens: &[Token<'_, TokenType>], separator: &str) -> Vec<usize> {
+    text.match_indices(separator)
+        .map(|(offset, _)| token_index(tokens, offset))
+        .collect()
+}
+
+/// 1-based line and column (in characters) of the byte `offset` in `text`.
+fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Location and text of the first differing token on each side, or `None` when the token streams
+/// are the same.
+fn first_difference(
+    left_text: &str,
+    left: &[Token<TokenType>],
+    right_text: &str,
+    right: &[Token<TokenType>],
+) -> Option<String> {
+    let index = left
+        .iter()
+        .zip(right.iter())
+        .position(|(l, r)| l.t != r.t || l.text() != r.text())
+        .unwrap_or(left.len().min(right.len()));
+    if index == left.len() && index == right.len() {
+        return None;
+    }
+    let describe = |text: &str, token: Option<&Token<TokenType>>| match token {
+        None => {
+            let (line, col) = line_col(text, text.len());
+            format!("{}:{} end of input", line, col)
+        }
+        Some(token) => {
+            let (line, col) = (token.line, token.col + 1);
+            let what = match token.t {
+                TokenType::BlockStart(_) => "start of block".to_string(),
+                TokenType::BlockEnd(_) => "end of block".to_string(),
+                _ => format!("{:?}", token.text()),
+            };
+            format!("{}:{} {}", line, col, what)
+        }
+    };
+    Some(format!(
+        "left:  {}\nright: {}\n",
+        describe(left_text, left.get(index)),
+        describe(right_text, right.get(index))
+    ))
+}
+
+/// Splits `text` at the only line equal to `marker` into the left and right version.
+fn split_combined(text: &str, marker: &str) -> Result<(String, String), String> {
+    let mut offset = 0;
+    let mut found = vec![];
+    for line in text.split_inclusive('\n') {
+        if line.trim_end_matches(['\n', '\r']) == marker {
+            found.push((offset, offset + line.len()));
         }
+        offset += line.len();
+    }
+    match found.as_slice() {
+        [(start, end)] => Ok((text[..*start].to_string(), text[*end..].to_string())),
+        [] => Err(format!("marker line {:?} not found", marker)),
+        _ => Err(format!(
+            "marker line {:?} found {} times, expected exactly once",
+            marker,
+            found.len()
+        )),
     }
+}
+
+/// Whether `output` is the same file as one of the inputs, which creating it would truncate.
+fn output_is_input(cli: &Cli, output: &Path) -> bool {
+    let Ok(output) = output.canonicalize() else {
+        // The output doesn't exist yet, so it can't be an input.
+        return false;
+    };
+    cli.left
+        .iter()
+        .chain(&cli.right)
+        .chain(&cli.base)
+        .chain(&cli.matrix)
+        .filter(|path| !is_stdin(path))
+        .any(|path| path.canonicalize().is_ok_and(|path| path == output))
+}
 
-    fn mutation_score(&self, left: &Token<'a, TokenType>, right: &Token<'a, TokenType>) -> f64 {
-        if left.t != right.t {
-            return self.mismatched_type_penalty;
+fn main() {
+    let cli = Cli::parse();
+    let color = if cli.no_color {
+        Color::Never
+    } else {
+        cli.color
+    };
+    colored::control::set_override(match color {
+        Color::Always => true,
+        Color::Auto => {
+            cli.output.is_none()
+                && io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").unwrap_or_default().is_empty()
         }
-        match left.t {
-            TokenType::BlockStart(indent) | TokenType::BlockEnd(indent) => match right.t {
-                TokenType::BlockStart(o_indent) | TokenType::BlockEnd(o_indent) => {
-                    // TODO: this is weird scoring. Indenting block should not penalize further
-                    // indentation changes in that block, only the start / end.
-                    indent.abs_diff(o_indent) as f64
+        Color::Never => false,
+    });
+    let mut out: Box<dyn Write> = match &cli.output {
+        Some(path) => {
+            if output_is_input(&cli, path) {
+                eprintln!("error: {}: output file is also an input", path.display());
+                std::process::exit(2);
+            }
+            match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(err) => {
+                    eprintln!("error: {}: {}", path.display(), err);
+                    std::process::exit(2);
                 }
-                _ => {
-                    panic!("This is impossible");
+            }
+        }
+        None => Box::new(io::stdout().lock()),
+    };
+    // Output is flushed before exiting, as `exit` doesn't run destructors.
+    match run(&cli, &mut out).and_then(|differ| out.flush().map(|_| differ)) {
+        Ok(false) => {}
+        Ok(true) => std::process::exit(1),
+        Err(err) => {
+            match &cli.output {
+                Some(path) => eprintln!("error: {}: {}", path.display(), err),
+                None => eprintln!("error: {}", err),
+            }
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Prints the diff of the inputs given on the command line. Returns whether they differ.
+fn run(cli: &Cli, out: &mut dyn Write) -> io::Result<bool> {
+    if !cli.matrix.is_empty() {
+        print_matrix(cli, out, &cli.matrix, cli.matrix_format)?;
+        return Ok(false);
+    }
+    if let Some(base) = &cli.base {
+        return merge(cli, out, base);
+    }
+    if let (Some(left), Some(right)) = (&cli.left, &cli.right) {
+        if left.is_dir() && right.is_dir() {
+            return diff_directories(cli, out, left, right);
+        }
+    }
+    // Names of the inputs for output headers.
+    let names;
+    let (left_text, right_text) = match cli.split_on.as_deref() {
+        Some(marker) => {
+            let path = cli.left.as_ref().unwrap();
+            names = (path.display().to_string(), path.display().to_string());
+            let text = read_text(path, cli.lossy_utf8);
+            split_combined(&text, marker).unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            })
+        }
+        None => {
+            let (left, right) = read_inputs(cli).unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            });
+            if left.is_binary() || right.is_binary() {
+                if left.bytes == right.bytes {
+                    return Ok(false);
                 }
-            },
-            TokenType::WhiteSpace | TokenType::SpecialCharacter | TokenType::Word => {
-                if left.text() == right.text() {
-                    0.
-                } else if left.text().to_lowercase() == right.text().to_lowercase() {
-                    self.mismatched_case_penalty
-                } else {
-                    self.mismatched_text_penalty
+                if cli.brief {
+                    writeln!(out, "Files {} and {} differ", left.name, right.name)?;
+                } else if !cli.quiet {
+                    writeln!(out, "Binary files {} and {} differ", left.name, right.name)?;
                 }
+                return Ok(true);
             }
+            names = (left.name.clone(), right.name.clone());
+            left.decode(cli.lossy_utf8)
+                .and_then(|left| Ok((left, right.decode(cli.lossy_utf8)?)))
+                .unwrap_or_else(|err| {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                })
+        }
+    };
+    diff(cli, out, left_text, right_text, names)
+}
+
+/// Merges the left and right file, both changed from `base`, and prints the result. Returns whether
+/// there are conflicts.
+fn merge(cli: &Cli, out: &mut dyn Write, base: &PathBuf) -> io::Result<bool> {
+    let (left, right) = (cli.left.as_ref().unwrap(), cli.right.as_ref().unwrap());
+    if [base, left, right].iter().filter(|x| is_stdin(x)).count() > 1 {
+        eprintln!("error: only one input can be read from the standard input");
+        std::process::exit(2);
+    }
+    let mut texts = [base, left, right].map(|path| read_text(path, cli.lossy_utf8));
+    if cli.strip_cr {
+        for text in &mut texts {
+            *text = text.replace("\r\n", "\n");
         }
     }
+    let [base_text, left_text, right_text] = &texts;
+    let ignore = |token: &Token<TokenType>| cli.ignore_token.iter().any(|x| x == token.text());
+    let mut base_tokens = Tokens::partition(tokenize(cli, base_text), ignore).tokens;
+    let mut left_tokens = Tokens::partition(tokenize(cli, left_text), ignore).tokens;
+    let mut right_tokens = Tokens::partition(tokenize(cli, right_text), ignore).tokens;
+    let mut interner = Interner::default();
+    interner.intern_tokens(&mut base_tokens);
+    interner.intern_tokens(&mut left_tokens);
+    interner.intern_tokens(&mut right_tokens);
+    for (path, tokens) in [
+        (base, &base_tokens),
+        (left, &left_tokens),
+        (right, &right_tokens),
+    ] {
+        check_max_tokens(cli, &path.display().to_string(), tokens.len());
+    }
+    for other in [&left_tokens, &right_tokens] {
+        let estimated = alignment::estimate_memory(base_tokens.len(), other.len());
+        if let Err(err) =
+            alignment::check_memory_budget(estimated, cli.memory_budget.saturating_mul(1024 * 1024))
+        {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    }
+    let regions = merge3::align3(&scoring(cli), &base_tokens, &left_tokens, &right_tokens);
+    let (merged, conflicts) = merge3::merge(
+        &regions,
+        Version {
+            text: left_text,
+            tokens: &left_tokens,
+        },
+        Version {
+            text: right_text,
+            tokens: &right_tokens,
+        },
+        (&left.display().to_string(), &right.display().to_string()),
+    );
+    write!(out, "{}", merged)?;
+    Ok(conflicts > 0)
 }
 
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    #[arg(short, long)]
-    debug: bool,
-    left: PathBuf,
-    right: PathBuf,
+/// Diffs files with the same relative path in both directories, and shows files only in one of
+/// them as entirely added or removed. Returns whether any file differs.
+fn diff_directories(cli: &Cli, out: &mut dyn Write, left: &Path, right: &Path) -> io::Result<bool> {
+    let files = |root: &Path| {
+        tree::files(root, &cli.exclude).unwrap_or_else(|err| {
+            eprintln!("error: {}: {}", root.display(), err);
+            std::process::exit(2);
+        })
+    };
+    let (left_files, right_files) = (files(left), files(right));
+    let mut has_changes = false;
+    for path in left_files.union(&right_files) {
+        // A file missing on one side is diffed against an empty one, like with `diff -rN`.
+        let read = |root: &Path, files: &BTreeSet<PathBuf>| {
+            if files.contains(path) {
+                read_input(&root.join(path)).unwrap_or_else(|err| {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                })
+            } else {
+                Input {
+                    name: root.join(path).display().to_string(),
+                    bytes: vec![],
+                }
+            }
+        };
+        let (left, right) = (read(left, &left_files), read(right, &right_files));
+        if left.bytes == right.bytes {
+            continue;
+        }
+        if left.is_binary() || right.is_binary() {
+            has_changes = true;
+            if cli.brief {
+                writeln!(out, "Files {} and {} differ", left.name, right.name)?;
+            } else if !cli.quiet {
+                writeln!(out, "Binary files {} and {} differ", left.name, right.name)?;
+            }
+            continue;
+        }
+        if cli.quiet {
+            has_changes = true;
+            continue;
+        }
+        if !cli.brief {
+            writeln!(
+                out,
+                "{}",
+                format!("diff {} {}", left.name, right.name).bold()
+            )?;
+        }
+        let names = (left.name.clone(), right.name.clone());
+        let texts = left
+            .decode(cli.lossy_utf8)
+            .and_then(|left| Ok((left, right.decode(cli.lossy_utf8)?)));
+        match texts {
+            Ok((left_text, right_text)) => {
+                // With `--brief`, files which differ only in ignored ways are not reported.
+                let differ = diff(cli, out, left_text, right_text, names)?;
+                has_changes |= differ || !cli.brief;
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+    Ok(has_changes)
 }
 
-fn main() {
-    let cli = Cli::parse();
-    let left_text = std::fs::read_to_string(cli.left).unwrap();
-    let right_text = std::fs::read_to_string(cli.right).unwrap();
-    let (left_tokens, left_whitespaces): (Vec<_>, Vec<_>) =
-        TokenParser::parse(&left_text).partition(|x| !x.is_whitespace());
-    let (right_tokens, right_whitespaces): (Vec<_>, Vec<_>) =
-        TokenParser::parse(&right_text).partition(|x| !x.is_whitespace());
+/// Cost of each aligned operation and the totals per kind of operation, for tuning the scoring.
+/// Interleaved whitespace and ignored tokens are left out, as they are not part of the alignment.
+fn print_costs(
+    out: &mut dyn Write,
+    scoring: &AffineScoring,
+    alignment: &Alignment<Token<'_, TokenType>>,
+) -> io::Result<()> {
+    let operations: Vec<_> = alignment
+        .operations()
+        .iter()
+        .filter(|operation| {
+            !operation
+                .left()
+                .or(operation.right())
+                .map(|token| token.is_whitespace() || token.is_ignored())
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect();
+    let costs = alignment::operation_costs(scoring, &operations);
+    // Count and cost of mutations, equal mutations, deletions and insertions.
+    let mut totals = [(0, 0.); 4];
+    writeln!(out, "operation costs:")?;
+    for (operation, cost) in operations.iter().zip(&costs) {
+        let (kind, description) = match operation {
+            AlignmentOperation::Mutation { left, right } if left.text() == right.text() => {
+                (1, format!("equal {:?}", right.text()))
+            }
+            AlignmentOperation::Mutation { left, right } => (
+                0,
+                format!("mutation {:?} -> {:?}", left.text(), right.text()),
+            ),
+            AlignmentOperation::InsertLeft { left } => {
+                (2, format!("insert_left {:?}", left.text()))
+            }
+            AlignmentOperation::InsertRight { right } => {
+                (3, format!("insert_right {:?}", right.text()))
+            }
+        };
+        totals[kind].0 += 1;
+        totals[kind].1 += cost;
+        // Adding zero turns `-0` of matches without extend bonus into `0`.
+        writeln!(out, "  {:+8.3} {}", cost + 0., description)?;
+    }
+    for (name, (count, cost)) in ["mutation", "equal", "insert_left", "insert_right"]
+        .iter()
+        .zip(totals)
+    {
+        writeln!(out, "{}: count {}, cost {:.3}", name, count, cost + 0.)?;
+    }
+    writeln!(
+        out,
+        "score: {} (sum of operation costs {})",
+        alignment.score(),
+        costs.iter().fold(0., |total, x| total + x)
+    )
+}
+
+/// Aligns the two texts and prints the diff. Returns whether they differ.
+fn diff(
+    cli: &Cli,
+    out: &mut dyn Write,
+    mut left_text: String,
+    mut right_text: String,
+    mut names: (String, String),
+) -> io::Result<bool> {
+    if cli.strip_cr {
+        left_text = left_text.replace("\r\n", "\n");
+        right_text = right_text.replace("\r\n", "\n");
+    }
+    // Without options that make different texts equal, comparing the texts is enough.
+    if cli.brief
+        && !cli.ignore_whitespace
+        && !cli.ignore_case
+        && cli.ignore_token.is_empty()
+        && cli.ignore_matching.is_none()
+    {
+        let differ = left_text != right_text;
+        if differ {
+            writeln!(out, "Files {} and {} differ", names.0, names.1)?;
+        }
+        return Ok(differ);
+    }
+    let ignore = |token: &Token<TokenType>| cli.ignore_token.iter().any(|x| x == token.text());
+    if cli.first_diff {
+        let left_tokens = Tokens::partition(tokenize(cli, &left_text), ignore).tokens;
+        let right_tokens = Tokens::partition(tokenize(cli, &right_text), ignore).tokens;
+        if let Some(difference) =
+            first_difference(&left_text, &left_tokens, &right_text, &right_tokens)
+        {
+            write!(out, "{}", difference)?;
+            return Ok(true);
+        }
+        return Ok(false);
+    }
+    let format = if cli.plain {
+        Format::Normal
+    } else {
+        cli.format
+    };
+    // Notices about empty inputs are only shown in the pretty output, as they would break the
+    // machine readable formats.
+    let notice = format == Format::Pretty && !cli.stat && !cli.quiet && !cli.brief;
+    let one_side_empty = left_text.is_empty() != right_text.is_empty();
+    if right_text.is_empty() && one_side_empty {
+        if cli.swap_on_empty {
+            std::mem::swap(&mut left_text, &mut right_text);
+            std::mem::swap(&mut names.0, &mut names.1);
+            if notice {
+                writeln!(
+                    out,
+                    "right file is empty — showing all left content (sides swapped)"
+                )?;
+            }
+        } else if notice {
+            writeln!(
+                out,
+                "right file is empty — showing all left content as removed"
+            )?;
+        }
+    } else if left_text.is_empty() && one_side_empty && notice {
+        writeln!(
+            out,
+            "left file is empty — showing all right content as added"
+        )?;
+    }
+    let Tokens {
+        tokens: mut left_tokens,
+        whitespace: left_whitespaces,
+    } = Tokens::partition(tokenize(cli, &left_text), ignore);
+    let Tokens {
+        tokens: mut right_tokens,
+        whitespace: right_whitespaces,
+    } = Tokens::partition(tokenize(cli, &right_text), ignore);
+    // Tokens are compared by interned ids in the alignment, which is faster than comparing texts.
+    let mut interner = Interner::default();
+    interner.intern_tokens(&mut left_tokens);
+    interner.intern_tokens(&mut right_tokens);
     // TODO: removal of whitespace tokens should be implementation detail of align?
-    let scoring = AffineScoring {
-        start_insert: 0.7,
-        extend_insert: 0.3,
-        block_end_insert_penalty: 1.,
-        mismatched_type_penalty: 100.,
-        mismatched_text_penalty: 1.,
-        mismatched_case_penalty: 0.01,
+    let scoring = scoring(cli);
+    // Identical inputs align trivially, which matters for directory diffs of mostly unchanged files.
+    let identical = left_text == right_text;
+    if !identical {
+        check_max_tokens(cli, &names.0, left_tokens.len());
+        check_max_tokens(cli, &names.1, right_tokens.len());
+    }
+    let use_line_anchors = match cli.line_anchors {
+        LineAnchors::Always => true,
+        LineAnchors::Never => false,
+        LineAnchors::Auto => {
+            left_tokens.len().saturating_mul(right_tokens.len()) > LINE_ANCHORS_AUTO_CELLS
+        }
+    };
+    let splits: Vec<_> = match cli.record_separator.as_deref() {
+        Some(separator) if !separator.is_empty() => {
+            let left_starts = record_starts(&left_text, &left_tokens, separator);
+            let right_starts = record_starts(&right_text, &right_tokens, separator);
+            (0..left_starts.len().max(right_starts.len()))
+                .map(|i| {
+                    (
+                        left_starts.get(i).copied().unwrap_or(left_tokens.len()),
+                        right_starts.get(i).copied().unwrap_or(right_tokens.len()),
+                    )
+                })
+                .collect()
+        }
+        _ if cli.block_align => blocks::block_splits(&scoring, &left_tokens, &right_tokens),
+        _ if cli.granularity == Granularity::Sentence => sentence_splits(
+            cli,
+            &scoring,
+            (&left_text, &left_tokens),
+            (&right_text, &right_tokens),
+        ),
+        _ if cli.algorithm == Algorithm::Patience => {
+            anchors::unique_line_anchors(&left_text, &right_text)
+                .into_iter()
+                .flat_map(|(left, right)| {
+                    // Anchored lines are segments of their own, so they can only match each other.
+                    [
+                        (left, right),
+                        (
+                            anchors::line_end(&left_text, left),
+                            anchors::line_end(&right_text, right),
+                        ),
+                    ]
+                })
+                .map(|(left, right)| {
+                    (
+                        token_index(&left_tokens, left),
+                        token_index(&right_tokens, right),
+                    )
+                })
+                .collect()
+        }
+        _ if use_line_anchors => anchors::unique_line_anchors(&left_text, &right_text)
+            .into_iter()
+            .map(|(left, right)| {
+                (
+                    token_index(&left_tokens, left),
+                    token_index(&right_tokens, right),
+                )
+            })
+            .collect(),
+        _ => vec![],
+    };
+    for (left_range, right_range) in
+        alignment::segments(left_tokens.len(), right_tokens.len(), &splits)
+    {
+        let estimated = if identical {
+            continue;
+        } else if cli.algorithm == Algorithm::Myers {
+            // Depends on the number of differences, which isn't known before aligning.
+            continue;
+        } else if cli.linear_memory {
+            alignment::estimate_linear_memory(left_range.len())
+        } else if cli.low_memory {
+            alignment::estimate_low_memory(left_range.len(), right_range.len())
+        } else {
+            alignment::estimate_memory(left_range.len(), right_range.len())
+        };
+        if let Err(err) =
+            alignment::check_memory_budget(estimated, cli.memory_budget.saturating_mul(1024 * 1024))
+        {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    }
+    let mut stats = AlignmentStats::default();
+    let started = std::time::Instant::now();
+    #[cfg(feature = "parallel")]
+    let parallel = cli.parallel;
+    #[cfg(not(feature = "parallel"))]
+    let parallel = false;
+    let mut alignment = if identical {
+        alignment::align_identical(&scoring, &left_tokens, &right_tokens)
+    } else if cli.algorithm == Algorithm::Myers {
+        align_segments(
+            |left, right| alignment::align_myers_with_stats(&scoring, left, right, &mut stats),
+            &left_tokens,
+            &right_tokens,
+            &splits,
+        )
+    } else if parallel {
+        #[cfg(feature = "parallel")]
+        {
+            align_segments(
+                |left, right| {
+                    alignment::align_parallel_with_stats(&scoring, left, right, &mut stats)
+                },
+                &left_tokens,
+                &right_tokens,
+                &splits,
+            )
+        }
+        #[cfg(not(feature = "parallel"))]
+        unreachable!()
+    } else if let Some(band) = cli.band {
+        align_segments(
+            |left, right| align_banded_with_stats(&scoring, left, right, band, &mut stats),
+            &left_tokens,
+            &right_tokens,
+            &splits,
+        )
+    } else if cli.linear_memory {
+        align_segments(
+            |left, right| align_linear_with_stats(&scoring, left, right, &mut stats),
+            &left_tokens,
+            &right_tokens,
+            &splits,
+        )
+    } else if cli.low_memory {
+        align_segments(
+            |left, right| align_low_memory_with_stats(&scoring, left, right, &mut stats),
+            &left_tokens,
+            &right_tokens,
+            &splits,
+        )
+    } else {
+        align_segments(
+            |left, right| align_with_stats(&scoring, left, right, &mut stats),
+            &left_tokens,
+            &right_tokens,
+            &splits,
+        )
     };
-    let alignment = align(&scoring, &left_tokens, &right_tokens)
-        .interleave_tokens(&left_whitespaces, &right_whitespaces);
+    if cli.verbose {
+        eprintln!(
+            "aligned {} x {} tokens in {} segments in {:.3?}: {} cells, {} back pointers, peak row \
+             of {} cells, score {}",
+            left_tokens.len(),
+            right_tokens.len(),
+            splits.len() + 1,
+            started.elapsed(),
+            stats.cells,
+            stats.back_pointers,
+            stats.peak_row,
+            alignment.score()
+        );
+    }
+    match cli.cleanup {
+        Cleanup::None => {}
+        Cleanup::Semantic => alignment.cleanup_semantic(),
+        Cleanup::Boundaries => alignment.snap_boundaries(&scoring),
+    }
+    // Whitespace is not aligned, so only differences in other tokens count as changes.
+    let has_changes = one_side_empty
+        || alignment.has_changes_by(|left, right| {
+            left == right
+                || (cli.ignore_case && left.to_lowercase() == right.to_lowercase())
+                || cli
+                    .ignore_matching
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(left) && regex.is_match(right))
+        });
+    if cli.brief && has_changes {
+        writeln!(out, "Files {} and {} differ", names.0, names.1)?;
+    }
+    if cli.quiet || cli.brief {
+        return Ok(has_changes);
+    }
+    let alignment = alignment.interleave_tokens(&left_whitespaces, &right_whitespaces);
     if cli.debug {
-        println!("{:#?}", alignment);
+        writeln!(out, "{:#?}", alignment)?;
+        writeln!(
+            out,
+            "matched tokens: {} (at most {})",
+            alignment
+                .lcs()
+                .into_iter()
+                .filter(|x| !x.is_whitespace())
+                .count(),
+            lcs::token_lcs(&left_tokens, &right_tokens).len()
+        )?;
+        print_costs(out, &scoring, &alignment)?;
+    }
+    let options = RenderOptions::default()
+        .with_collapse_block_changes(cli.collapse_block_changes)
+        .with_detect_moves(cli.detect_moves)
+        .with_refine_words(cli.refine_words)
+        .with_expand_tabs(cli.expand_tabs || cli.tab_width.is_some())
+        .with_tab_width(cli.tab_width.unwrap_or(TAB_WIDTH))
+        .with_initial_tab(cli.initial_tab)
+        .with_emphasis(cli.emphasize)
+        .with_context(cli.context)
+        .with_isolate_bidi(cli.bidi_isolate)
+        .with_show_case_changes(cli.show_case_changes)
+        .with_ignore_case(cli.ignore_case)
+        .with_ignore_matching(cli.ignore_matching.clone())
+        .with_whole_lines(cli.granularity == Granularity::Line)
+        .with_squeeze_blank(cli.squeeze_blank)
+        .with_only(cli.only)
+        .with_side_by_side(cli.side_by_side.then(|| width::output_width(cli.width)))
+        .with_markers(Markers {
+            same: cli.marker_same.clone(),
+            del: cli.marker_del.clone(),
+            add: cli.marker_add.clone(),
+        });
+    let line_diff = || {
+        let mut line_diff = alignment.line_diff_with(cli.ignore_whitespace, cli.ignore_case);
+        line_diff.ignore_blank_lines = cli.ignore_blank_lines;
+        line_diff
+    };
+    if cli.stat {
+        write!(
+            out,
+            "{}",
+            line_diff().stat(&names.1, width::output_width(cli.width))
+        )?;
+    } else {
+        match format {
+            Format::Pretty => alignment.write_pretty(&options, out)?,
+            Format::Normal => write!(out, "{}", line_diff().normal(&options))?,
+            Format::Unified => write!(
+                out,
+                "{}",
+                line_diff().unified(
+                    &names.0,
+                    &names.1,
+                    cli.context.unwrap_or(UNIFIED_CONTEXT),
+                    &options
+                )
+            )?,
+            Format::Ed => write!(out, "{}", line_diff().ed_script())?,
+            Format::Json => {
+                let operations: Vec<_> = alignment
+                    .operations()
+                    .iter()
+                    .map(|operation| operation.map(|token| token.view()))
+                    .collect();
+                writeln!(out, "{}", serde_json::to_string(&operations).unwrap())?;
+            }
+            Format::Html => write!(out, "{}", alignment.html(&options))?,
+            Format::ColorWords => write!(out, "{}", alignment.color_words(&options))?,
+        }
+    }
+    if cli.stats {
+        writeln!(out, "{}", alignment.stats())?;
     }
-    alignment.pretty();
+    Ok(has_changes)
 }
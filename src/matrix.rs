@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use crate::alignment::score_only;
+use crate::types::AlignmentScoring;
+
+/// Similarity of two token sequences in `0..=1`. The score is normalized by the cost of deleting
+/// everything from `left` and inserting everything from `right`, which is the worst possible
+/// alignment. Identical (or both empty) sequences have similarity 1.
+pub fn similarity<T, S: AlignmentScoring<T>>(scoring: &S, left: &[T], right: &[T]) -> f64 {
+    let worst = score_only(scoring, left, &[]) + score_only(scoring, &[], right);
+    if worst <= 0. {
+        return 1.;
+    }
+    (1. - score_only(scoring, left, right) / worst).clamp(0., 1.)
+}
+
+/// Computes pairwise similarities of all documents. Only the upper triangle is computed (in
+/// parallel) and mirrored, so the matrix is symmetric and has 1.0 on the diagonal.
+pub fn similarity_matrix<T: Sync, S: AlignmentScoring<T> + Sync>(
+    scoring: &S,
+    documents: &[Vec<T>],
+) -> Vec<Vec<f64>> {
+    let n = documents.len();
+    let pairs: Vec<(usize, usize)> = (0..n)
+        .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+        .collect();
+    let threads = std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1);
+    let chunk_size = pairs.len().div_ceil(threads).max(1);
+    let scores: Vec<((usize, usize), f64)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = pairs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&(i, j)| ((i, j), similarity(scoring, &documents[i], &documents[j])))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    let mut matrix = vec![vec![1.; n]; n];
+    for ((i, j), score) in scores {
+        matrix[i][j] = score;
+        matrix[j][i] = score;
+    }
+    matrix
+}
+
+fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn to_csv(files: &[PathBuf], matrix: &[Vec<f64>]) -> String {
+    let names: Vec<String> = files
+        .iter()
+        .map(|file| csv_escape(&file.to_string_lossy()))
+        .collect();
+    let mut out = format!(",{}\n", names.join(","));
+    for (name, row) in names.iter().zip(matrix) {
+        let row: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+        out.push_str(&format!("{},{}\n", name, row.join(",")));
+    }
+    out
+}
+
+pub fn to_json(files: &[PathBuf], matrix: &[Vec<f64>]) -> String {
+    let names: Vec<String> = files
+        .iter()
+        .map(|file| json_escape(&file.to_string_lossy()))
+        .collect();
+    let rows: Vec<String> = matrix
+        .iter()
+        .map(|row| {
+            let row: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+            format!("[{}]", row.join(","))
+        })
+        .collect();
+    format!(
+        "{{\"files\":[{}],\"matrix\":[{}]}}\n",
+        names.join(","),
+        rows.join(",")
+    )
+}
@@ -0,0 +1,285 @@
+use std::ops::Range;
+
+use crate::alignment::{align, AlignmentOperation};
+use crate::cleanup::same;
+use crate::types::{AlignmentScoring, Token};
+
+/// How a region of a three way alignment changed from the base version.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    /// Same tokens in all three versions.
+    Unchanged,
+    /// Changed only in the left version.
+    Left,
+    /// Changed only in the right version.
+    Right,
+    /// Changed the same way in both versions.
+    Both,
+    /// Changed differently in both versions.
+    Conflict,
+}
+
+/// Consecutive tokens of the three versions, as ranges of token indices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub base: Range<usize>,
+    pub left: Range<usize>,
+    pub right: Range<usize>,
+}
+
+/// For each base token, the index of the token of `other` it's aligned to, if that one is the
+/// same.
+fn matches<T: Token, S: AlignmentScoring<T>>(
+    scoring: &S,
+    base: &[T],
+    other: &[T],
+) -> Vec<Option<usize>> {
+    let mut matched = vec![None; base.len()];
+    let (mut b, mut o) = (0, 0);
+    for operation in align(scoring, base, other).operations() {
+        match operation {
+            AlignmentOperation::Mutation { left, right } => {
+                if same(*left, *right) {
+                    matched[b] = Some(o);
+                }
+                b += 1;
+                o += 1;
+            }
+            AlignmentOperation::InsertLeft { .. } => b += 1,
+            AlignmentOperation::InsertRight { .. } => o += 1,
+        }
+    }
+    matched
+}
+
+/// Three way alignment of `left` and `right`, which were both changed from `base`, as for a
+/// merge. Both are aligned to `base`, and base tokens which are aligned to the same token on both
+/// sides are unchanged, as in diff3. The tokens between two unchanged regions form one region,
+/// classified by comparing its tokens in the three versions. Regions cover all tokens of all
+/// versions in order.
+pub fn align3<T: Token, S: AlignmentScoring<T>>(
+    scoring: &S,
+    base: &[T],
+    left: &[T],
+    right: &[T],
+) -> Vec<Region> {
+    let in_left = matches(scoring, base, left);
+    let in_right = matches(scoring, base, right);
+    let equal = |a: &[T], b: &[T]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b));
+    let mut regions: Vec<Region> = vec![];
+    let mut push = |region: Region| match regions.last_mut() {
+        Some(last) if last.kind == region.kind => {
+            last.base.end = region.base.end;
+            last.left.end = region.left.end;
+            last.right.end = region.right.end;
+        }
+        _ => regions.push(region),
+    };
+    let (mut b, mut l, mut r) = (0, 0, 0);
+    while b < base.len() || l < left.len() || r < right.len() {
+        let start = (b, l, r);
+        while b < base.len() && in_left[b] == Some(l) && in_right[b] == Some(r) {
+            b += 1;
+            l += 1;
+            r += 1;
+        }
+        if b > start.0 {
+            push(Region {
+                kind: RegionKind::Unchanged,
+                base: start.0..b,
+                left: start.1..l,
+                right: start.2..r,
+            });
+        }
+        // The changed region ends at the next base token which is unchanged on both sides.
+        let next = (b..base.len())
+            .find_map(|i| Some((i, in_left[i]?, in_right[i]?)))
+            .unwrap_or((base.len(), left.len(), right.len()));
+        if next == (b, l, r) {
+            continue;
+        }
+        let (base_part, left_part, right_part) =
+            (&base[b..next.0], &left[l..next.1], &right[r..next.2]);
+        let kind = match (equal(base_part, left_part), equal(base_part, right_part)) {
+            (true, true) => RegionKind::Unchanged,
+            (true, false) => RegionKind::Right,
+            (false, true) => RegionKind::Left,
+            (false, false) if equal(left_part, right_part) => RegionKind::Both,
+            (false, false) => RegionKind::Conflict,
+        };
+        push(Region {
+            kind,
+            base: b..next.0,
+            left: l..next.1,
+            right: r..next.2,
+        });
+        (b, l, r) = next;
+    }
+    regions
+}
+
+/// Text of a version with the tokens it was aligned by.
+#[derive(Clone, Copy)]
+pub struct Version<'a, T> {
+    pub text: &'a str,
+    pub tokens: &'a [T],
+}
+
+impl<'a, T: Token> Version<'a, T> {
+    /// Byte range of the text of a range of tokens, including the whitespace up to the next token,
+    /// so the ranges of consecutive regions cover the whole text. Indentation belongs to the token
+    /// after it, so ranges of regions starting a line start with the line.
+    fn bytes(&self, tokens: &Range<usize>) -> Range<usize> {
+        let offset = |index: usize| match index {
+            0 => 0,
+            index if index < self.tokens.len() => {
+                let start = self.tokens[index].start();
+                let before =
+                    self.text[..start].trim_end_matches(|c: char| c.is_whitespace() && c != '\n');
+                if before.ends_with('\n') {
+                    before.len()
+                } else {
+                    start
+                }
+            }
+            _ => self.text.len(),
+        };
+        offset(tokens.start)..offset(tokens.end)
+    }
+
+    /// Whether the byte offset is at the start of a line.
+    fn at_line_start(&self, offset: usize) -> bool {
+        offset == 0 || self.text[..offset].ends_with('\n')
+    }
+}
+
+/// Conflicting regions of a merge, widened to whole lines, as ranges of region indices and the
+/// byte ranges of their text in both versions. The first and last region may be included only
+/// partly.
+struct Conflict {
+    regions: Range<usize>,
+    left: Range<usize>,
+    right: Range<usize>,
+}
+
+/// Merged text of the regions of `align3` of the left and right version, with changes taken from
+/// the side which changed them, and the number of conflicts. Unchanged regions are taken from the
+/// left version, so differences in whitespace only in the right one are lost. Conflicts are
+/// widened to whole lines and shown like git does, the left version between
+/// `<<<<<<< left_label` and `=======` and the right one between that and `>>>>>>> right_label`.
+pub fn merge<T: Token>(
+    regions: &[Region],
+    left: Version<'_, T>,
+    right: Version<'_, T>,
+    (left_label, right_label): (&str, &str),
+) -> (String, usize) {
+    let bytes = |index: usize| {
+        let region = &regions[index];
+        (left.bytes(&region.left), right.bytes(&region.right))
+    };
+    let mut conflicts: Vec<Conflict> = vec![];
+    for (index, region) in regions.iter().enumerate() {
+        if region.kind != RegionKind::Conflict {
+            continue;
+        }
+        let (mut start, mut end) = (index, index + 1);
+        let (left_bytes, right_bytes) = bytes(index);
+        let (mut left_start, mut right_start) = (left_bytes.start, right_bytes.start);
+        let (mut left_end, mut right_end) = (left_bytes.end, right_bytes.end);
+        // Take in preceding regions until a line starts in both versions, splitting the last one
+        // taken in at the line start if possible.
+        while !(left.at_line_start(left_start) && right.at_line_start(right_start)) {
+            start -= 1;
+            let (left_bytes, right_bytes) = bytes(start);
+            let line_start = |text: &str, range: &Range<usize>| {
+                text[range.clone()].rfind('\n').map(|x| range.start + x + 1)
+            };
+            match (
+                line_start(left.text, &left_bytes),
+                line_start(right.text, &right_bytes),
+            ) {
+                (Some(left_line), Some(right_line)) => {
+                    (left_start, right_start) = (left_line, right_line);
+                }
+                _ => (left_start, right_start) = (left_bytes.start, right_bytes.start),
+            }
+        }
+        // Likewise for the following regions, until a line ends in both versions.
+        while !(left_end == left.text.len() || left.text[..left_end].ends_with('\n'))
+            || !(right_end == right.text.len() || right.text[..right_end].ends_with('\n'))
+        {
+            let (left_bytes, right_bytes) = bytes(end);
+            end += 1;
+            let line_end = |text: &str, range: &Range<usize>| {
+                text[range.clone()].find('\n').map(|x| range.start + x + 1)
+            };
+            match (
+                line_end(left.text, &left_bytes),
+                line_end(right.text, &right_bytes),
+            ) {
+                (Some(left_line), Some(right_line)) => {
+                    (left_end, right_end) = (left_line, right_line)
+                }
+                _ => (left_end, right_end) = (left_bytes.end, right_bytes.end),
+            }
+        }
+        match conflicts.last_mut() {
+            Some(last) if last.regions.end > start => {
+                last.regions.end = end;
+                last.left.end = left_end;
+                last.right.end = right_end;
+            }
+            _ => conflicts.push(Conflict {
+                regions: start..end,
+                left: left_start..left_end,
+                right: right_start..right_end,
+            }),
+        }
+    }
+    // Text of one version of a conflict, ending with a line break.
+    let text = |text: &str| {
+        let mut text = text.to_string();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    };
+    let mut out = String::new();
+    let mut conflict = conflicts.iter().peekable();
+    for (index, region) in regions.iter().enumerate() {
+        while conflict.next_if(|x| x.regions.end <= index).is_some() {}
+        let (version, bytes, conflict_bytes) = match region.kind {
+            RegionKind::Right => (
+                &right.text,
+                right.bytes(&region.right),
+                conflict.peek().map(|x| &x.right),
+            ),
+            _ => (
+                &left.text,
+                left.bytes(&region.left),
+                conflict.peek().map(|x| &x.left),
+            ),
+        };
+        let Some(current) = conflict.peek().filter(|x| x.regions.contains(&index)) else {
+            out.push_str(&version[bytes]);
+            continue;
+        };
+        let conflict_bytes = conflict_bytes.unwrap();
+        // Parts of the first and last region of the conflict outside of it.
+        if region.kind != RegionKind::Conflict && bytes.start < conflict_bytes.start {
+            out.push_str(&version[bytes.start..bytes.end.min(conflict_bytes.start)]);
+        }
+        if index == current.regions.start {
+            out.push_str(&format!("<<<<<<< {}\n", left_label));
+            out.push_str(&text(&left.text[current.left.clone()]));
+            out.push_str("=======\n");
+            out.push_str(&text(&right.text[current.right.clone()]));
+            out.push_str(&format!(">>>>>>> {}\n", right_label));
+        }
+        if region.kind != RegionKind::Conflict && bytes.end > conflict_bytes.end {
+            out.push_str(&version[bytes.start.max(conflict_bytes.end)..bytes.end]);
+        }
+    }
+    (out, conflicts.len())
+}
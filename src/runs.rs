@@ -0,0 +1,63 @@
+use crate::alignment::{Alignment, AlignmentOperation, RenderOptions};
+use crate::types::Token;
+
+/// How a run of text of the right document is shown by the inline outputs.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Class {
+    Same,
+    Deleted,
+    Inserted,
+}
+
+impl<'a, T: Token> Alignment<'a, T> {
+    /// Right document as runs of text of the same class, merged so adjacent tokens share the
+    /// markup, for `html` and `color_words`. Whitespace of the right document is kept as it is,
+    /// deleted whitespace is a single space. Mutations which `options` show as unchanged, e.g.
+    /// case changes with `ignore_case`, are unchanged text of the right document.
+    pub(crate) fn runs(&self, options: &RenderOptions) -> Vec<(Class, String)> {
+        let mut runs: Vec<(Class, String)> = vec![];
+        let mut push = |class: Class, text: &str| match runs.last_mut() {
+            Some((last, run)) if *last == class => run.push_str(text),
+            _ => runs.push((class, text.to_string())),
+        };
+        let mut prev_was_space = true;
+        for operation in self.operations() {
+            prev_was_space = match operation {
+                AlignmentOperation::Mutation { left, right }
+                    if left.text() == right.text()
+                        || options.shows_as_same(left.text(), right.text()) =>
+                {
+                    push(Class::Same, right.text());
+                    right.is_whitespace()
+                }
+                AlignmentOperation::Mutation { left, right } => {
+                    push(Class::Deleted, left.text());
+                    push(Class::Inserted, right.text());
+                    false
+                }
+                AlignmentOperation::InsertLeft { left } if left.is_whitespace() => {
+                    if !prev_was_space {
+                        push(Class::Deleted, " ");
+                    }
+                    true
+                }
+                AlignmentOperation::InsertLeft { left } if left.is_ignored() => prev_was_space,
+                AlignmentOperation::InsertLeft { left } => {
+                    push(Class::Deleted, left.text());
+                    false
+                }
+                AlignmentOperation::InsertRight { right }
+                    if right.is_whitespace() || right.is_ignored() =>
+                {
+                    push(Class::Same, right.text());
+                    right.is_whitespace()
+                }
+                AlignmentOperation::InsertRight { right } => {
+                    push(Class::Inserted, right.text());
+                    false
+                }
+            };
+        }
+        runs
+    }
+}
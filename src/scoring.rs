@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use regex::Regex;
+
+use crate::tokenizer::{Token, TokenType};
+use crate::types::AlignmentScoring;
+
+pub const DEFAULT_START_INSERT: f64 = 0.7;
+pub const DEFAULT_EXTEND_INSERT: f64 = 0.3;
+pub const DEFAULT_BLOCK_END_PENALTY: f64 = 1.;
+pub const DEFAULT_MISMATCHED_TYPE_PENALTY: f64 = 100.;
+pub const DEFAULT_MISMATCHED_TEXT_PENALTY: f64 = 1.;
+/// Small, so with `ignore_case` a case change is preferred over any other change.
+pub const DEFAULT_MISMATCHED_CASE_PENALTY: f64 = 0.01;
+
+/// How the cost of a run of inserted or deleted tokens grows with its length.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum GapCost {
+    /// The start penalty for the first token and the extend penalty for each further one.
+    #[default]
+    Affine,
+    /// The start penalty plus the extend penalty times the binary logarithm of the run length, so
+    /// each further token of a long run costs less, e.g. for large inserted blocks of log lines.
+    Logarithmic,
+}
+
+/// Cost of starting and of extending an insert.
+#[derive(Clone, Copy, Debug)]
+pub struct InsertPenalty {
+    pub start: f64,
+    pub extend: f64,
+}
+
+/// Insert penalties per token type.
+#[derive(Clone, Copy, Debug)]
+pub struct InsertPenalties {
+    pub whitespace: InsertPenalty,
+    pub special_character: InsertPenalty,
+    pub word: InsertPenalty,
+    pub string_literal: InsertPenalty,
+    pub number: InsertPenalty,
+    pub comment: InsertPenalty,
+    pub block_start: InsertPenalty,
+    pub block_end: InsertPenalty,
+}
+
+impl InsertPenalties {
+    /// Same penalties for all token types, with `block_end_penalty` added for ends of blocks.
+    pub fn new(start: f64, extend: f64, block_end_penalty: f64) -> Self {
+        let penalty = InsertPenalty { start, extend };
+        InsertPenalties {
+            whitespace: penalty,
+            special_character: penalty,
+            word: penalty,
+            string_literal: penalty,
+            number: penalty,
+            comment: penalty,
+            block_start: penalty,
+            // Deleting or inserting ends of blocks is expensive, so blocks don't get merged.
+            block_end: InsertPenalty {
+                start: start + block_end_penalty,
+                extend: extend + block_end_penalty,
+            },
+        }
+    }
+
+    /// Penalties for inserting or deleting a token of type `t`. Sentences have the penalties of
+    /// words.
+    pub fn get(&self, t: &TokenType) -> &InsertPenalty {
+        match t {
+            TokenType::WhiteSpace => &self.whitespace,
+            TokenType::SpecialCharacter => &self.special_character,
+            TokenType::Word | TokenType::Sentence => &self.word,
+            TokenType::StringLiteral => &self.string_literal,
+            TokenType::Number => &self.number,
+            TokenType::Comment => &self.comment,
+            TokenType::BlockStart(_) => &self.block_start,
+            TokenType::BlockEnd(_) => &self.block_end,
+        }
+    }
+
+    /// Sets penalties from config keys `insert.<type>.start` and `insert.<type>.extend`, where
+    /// type is one of `whitespace`, `special`, `word`, `string`, `number`, `comment`,
+    /// `block_start` and `block_end`.
+    pub fn configure(&mut self, config: &HashMap<String, String>) -> Result<(), String> {
+        for (key, value) in config {
+            let Some(rest) = key.strip_prefix("insert.") else {
+                return Err(format!("unknown config key {:?}", key));
+            };
+            let (t, field) = rest
+                .rsplit_once('.')
+                .ok_or_else(|| format!("unknown config key {:?}", key))?;
+            let penalty = match t {
+                "whitespace" => &mut self.whitespace,
+                "special" => &mut self.special_character,
+                "word" => &mut self.word,
+                "string" => &mut self.string_literal,
+                "number" => &mut self.number,
+                "comment" => &mut self.comment,
+                "block_start" => &mut self.block_start,
+                "block_end" => &mut self.block_end,
+                _ => return Err(format!("unknown token type {:?} in {:?}", t, key)),
+            };
+            let value = parse_penalty(value).map_err(|err| format!("{} for {:?}", err, key))?;
+            match field {
+                "start" => penalty.start = value,
+                "extend" => penalty.extend = value,
+                _ => return Err(format!("unknown config key {:?}", key)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for InsertPenalties {
+    fn default() -> Self {
+        InsertPenalties::new(
+            DEFAULT_START_INSERT,
+            DEFAULT_EXTEND_INSERT,
+            DEFAULT_BLOCK_END_PENALTY,
+        )
+    }
+}
+
+/// Default scoring: inserts cost more to start than to extend (affine gap costs) and tokens of
+/// different types are effectively never aligned. Comparison is case sensitive unless
+/// `ignore_case` is set.
+#[derive(Clone, Debug)]
+pub struct AffineScoring {
+    pub insert_penalties: InsertPenalties,
+    pub mismatched_type_penalty: f64,
+    pub mismatched_text_penalty: f64,
+    /// Cost of aligning tokens which differ only in case, used only with `ignore_case`.
+    pub mismatched_case_penalty: f64,
+    /// Whether tokens differing only in case cost `mismatched_case_penalty` instead of
+    /// `mismatched_text_penalty`.
+    pub ignore_case: bool,
+    pub gap_cost: GapCost,
+    /// Subtracted from the score of a match directly following another mutation, so that
+    /// contiguous runs of matches are preferred.
+    pub match_extend_bonus: f64,
+    /// Number tokens whose values differ by at most this much cost the same as equal ones, so
+    /// `1.0`, `1.00` and `1` align. Numbers which don't parse as `f64` are compared as text.
+    pub numeric_tolerance: Option<f64>,
+    /// Tokens which both match this regex cost the same as equal ones, e.g. for timestamps or
+    /// generated ids. The regex is run on both tokens of every pair of different tokens the aligner
+    /// considers, which is most of the `left × right` cells, so aligning takes about twice as long
+    /// even with simple patterns, and longer with expensive ones.
+    pub ignore_matching: Option<Regex>,
+    /// Different words cost `mismatched_text_penalty` times their Levenshtein distance divided by
+    /// the length of the longer one, so similar words like `color` and `colour` pair up before
+    /// unrelated ones.
+    pub fuzzy_words: bool,
+    /// Costs of pairs of different words with `fuzzy_words`, by the interned ids of their texts.
+    /// Shared by clones, so they need to score tokens of the same `Interner`.
+    fuzzy_costs: Arc<Mutex<HashMap<(u32, u32), f64>>>,
+}
+
+impl Default for AffineScoring {
+    fn default() -> Self {
+        AffineScoring {
+            insert_penalties: InsertPenalties::default(),
+            mismatched_type_penalty: DEFAULT_MISMATCHED_TYPE_PENALTY,
+            mismatched_text_penalty: DEFAULT_MISMATCHED_TEXT_PENALTY,
+            mismatched_case_penalty: DEFAULT_MISMATCHED_CASE_PENALTY,
+            ignore_case: false,
+            gap_cost: GapCost::Affine,
+            match_extend_bonus: 0.,
+            numeric_tolerance: None,
+            ignore_matching: None,
+            fuzzy_words: false,
+            fuzzy_costs: Arc::default(),
+        }
+    }
+}
+
+impl AffineScoring {
+    pub fn with_insert_penalties(mut self, insert_penalties: InsertPenalties) -> Self {
+        self.insert_penalties = insert_penalties;
+        self
+    }
+
+    pub fn with_mismatched_type_penalty(mut self, penalty: f64) -> Self {
+        self.mismatched_type_penalty = penalty;
+        self
+    }
+
+    pub fn with_mismatched_text_penalty(mut self, penalty: f64) -> Self {
+        self.mismatched_text_penalty = penalty;
+        self
+    }
+
+    pub fn with_mismatched_case_penalty(mut self, penalty: f64) -> Self {
+        self.mismatched_case_penalty = penalty;
+        self
+    }
+
+    pub fn with_ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    pub fn with_gap_cost(mut self, gap_cost: GapCost) -> Self {
+        self.gap_cost = gap_cost;
+        self
+    }
+
+    pub fn with_match_extend_bonus(mut self, bonus: f64) -> Self {
+        self.match_extend_bonus = bonus;
+        self
+    }
+
+    pub fn with_numeric_tolerance(mut self, tolerance: Option<f64>) -> Self {
+        self.numeric_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_ignore_matching(mut self, regex: Option<Regex>) -> Self {
+        self.ignore_matching = regex;
+        self
+    }
+
+    pub fn with_fuzzy_words(mut self, fuzzy_words: bool) -> Self {
+        self.fuzzy_words = fuzzy_words;
+        self
+    }
+
+    /// Cost of pairing different words with `fuzzy_words`. Interned pairs are computed once.
+    fn fuzzy_cost(&self, left: &Token<'_, TokenType>, right: &Token<'_, TokenType>) -> f64 {
+        let cost = || {
+            let (left, right) = (left.source_text(), right.source_text());
+            let longer = left.chars().count().max(right.chars().count());
+            self.mismatched_text_penalty * levenshtein(left, right) as f64 / longer as f64
+        };
+        let (Some(left_symbol), Some(right_symbol)) = (left.symbol, right.symbol) else {
+            return cost();
+        };
+        let key = (left_symbol.text, right_symbol.text);
+        if let Some(&cached) = self.fuzzy_costs.lock().unwrap().get(&key) {
+            return cached;
+        }
+        let cost = cost();
+        self.fuzzy_costs.lock().unwrap().insert(key, cost);
+        cost
+    }
+
+    /// Whether both tokens are numbers within `numeric_tolerance` of each other.
+    fn numerically_equal(&self, left: &Token<'_, TokenType>, right: &Token<'_, TokenType>) -> bool {
+        let Some(tolerance) = self.numeric_tolerance else {
+            return false;
+        };
+        if left.t != TokenType::Number {
+            return false;
+        }
+        match (
+            left.source_text().parse::<f64>(),
+            right.source_text().parse::<f64>(),
+        ) {
+            (Ok(left), Ok(right)) => (left - right).abs() <= tolerance,
+            _ => false,
+        }
+    }
+
+    /// Whether both tokens match `ignore_matching`, whatever their types. Block tokens have no
+    /// text, so they never match.
+    fn both_ignored(&self, left: &Token<'_, TokenType>, right: &Token<'_, TokenType>) -> bool {
+        let is_block = |token: &Token<'_, TokenType>| {
+            matches!(token.t, TokenType::BlockStart(_) | TokenType::BlockEnd(_))
+        };
+        match &self.ignore_matching {
+            Some(regex) if !is_block(left) && !is_block(right) => {
+                regex.is_match(left.source_text()) && regex.is_match(right.source_text())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'a> AlignmentScoring<Token<'a, TokenType>> for AffineScoring {
+    fn insert_score(&self, inserted: &Token<'a, TokenType>, previous_is_same: bool) -> f64 {
+        let penalty = self.insert_penalties.get(&inserted.t);
+        if previous_is_same {
+            penalty.extend
+        } else {
+            penalty.start
+        }
+    }
+
+    fn insert_run_score(&self, inserted: &Token<'a, TokenType>, run_length: usize) -> f64 {
+        match self.gap_cost {
+            GapCost::Affine => self.insert_score(inserted, run_length > 1),
+            GapCost::Logarithmic if run_length > 1 => {
+                // Costs of the tokens of a run of `n` sum up to `start + extend * log2(n)`.
+                let penalty = self.insert_penalties.get(&inserted.t);
+                penalty.extend * (run_length as f64 / (run_length - 1) as f64).log2()
+            }
+            GapCost::Logarithmic => self.insert_penalties.get(&inserted.t).start,
+        }
+    }
+
+    /// Matches following another match get `match_extend_bonus` off.
+    fn min_mutation_score(&self) -> f64 {
+        (-self.match_extend_bonus).min(0.)
+    }
+
+    fn mutation_score(
+        &self,
+        left: &Token<'a, TokenType>,
+        right: &Token<'a, TokenType>,
+        previous_is_same: bool,
+    ) -> f64 {
+        let equal = if previous_is_same {
+            -self.match_extend_bonus
+        } else {
+            0.
+        };
+        // Block tokens of different nesting depth are a type mismatch too. Depth and not
+        // indentation is compared, so re-indenting a block doesn't penalize the blocks inside it.
+        if left.t != right.t {
+            return if self.both_ignored(left, right) {
+                equal
+            } else {
+                self.mismatched_type_penalty
+            };
+        }
+        match left.t {
+            TokenType::BlockStart(_) | TokenType::BlockEnd(_) => 0.,
+            TokenType::Sentence if left.same_text(right) => equal,
+            // Up to twice the text penalty, so sentences sharing few words are cheaper to delete
+            // and insert than to pair.
+            TokenType::Sentence => {
+                self.mismatched_text_penalty
+                    * 2.
+                    * (1. - word_overlap(left.lowercase(), right.lowercase()))
+            }
+            TokenType::WhiteSpace
+            | TokenType::SpecialCharacter
+            | TokenType::Word
+            | TokenType::StringLiteral
+            | TokenType::Number
+            | TokenType::Comment => {
+                if left.same_text(right)
+                    || self.numerically_equal(left, right)
+                    || self.both_ignored(left, right)
+                {
+                    equal
+                } else if self.ignore_case && left.same_lowercase(right) {
+                    self.mismatched_case_penalty
+                } else if self.fuzzy_words && left.t == TokenType::Word {
+                    self.fuzzy_cost(left, right)
+                } else {
+                    self.mismatched_text_penalty
+                }
+            }
+        }
+    }
+}
+
+/// Number of single character insertions, deletions and substitutions turning `left` into
+/// `right`.
+fn levenshtein(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut row: Vec<usize> = (0..=right.len()).collect();
+    for (i, l) in left.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &r) in right.iter().enumerate() {
+            let substitution = diagonal + usize::from(l != r);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[right.len()]
+}
+
+/// Number of occurrences of each word of the text.
+fn word_counts(text: &str) -> HashMap<&str, usize> {
+    let mut words = HashMap::new();
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+    {
+        *words.entry(word).or_default() += 1;
+    }
+    words
+}
+
+/// Dice coefficient of the multisets of words of both texts: 1 when they have the same words, 0
+/// when they share none.
+fn word_overlap(left: &str, right: &str) -> f64 {
+    let (left, right) = (word_counts(left), word_counts(right));
+    let total: usize = left.values().chain(right.values()).sum();
+    if total == 0 {
+        return 1.;
+    }
+    let shared: usize = left
+        .iter()
+        .map(|(word, count)| (*count).min(right.get(word).copied().unwrap_or(0)))
+        .sum();
+    2. * shared as f64 / total as f64
+}
+
+/// Non-negative number, as the alignment assumes penalties can't decrease the cost.
+pub fn parse_penalty(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(x) if x >= 0. => Ok(x),
+        Ok(_) => Err(format!("penalty {:?} is negative", value)),
+        Err(_) => Err(format!("invalid number {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::AlignmentOperation;
+    use crate::types::Token as _;
+    use crate::{align, Tokens};
+
+    fn score(scoring: &AffineScoring, left: &str, right: &str) -> f64 {
+        let (left, right) = (Tokens::new(left), Tokens::new(right));
+        align(scoring, &left.tokens, &right.tokens).score()
+    }
+
+    #[test]
+    fn identical_inputs_score_zero() {
+        let scoring = AffineScoring::default();
+        assert_eq!(score(&scoring, "", ""), 0.);
+        assert_eq!(score(&scoring, "fn main() {}", "fn main() {}"), 0.);
+        assert_eq!(score(&scoring.with_ignore_case(true), "a B c", "a B c"), 0.);
+    }
+
+    #[test]
+    fn inserted_token_costs_start_insert() {
+        let scoring = AffineScoring::default();
+        let base = score(&scoring, "let x = f(a);", "let y = f(a);");
+        assert_eq!(
+            score(&scoring, "let x = f(a);", "let y = f(a, b);") - base,
+            DEFAULT_START_INSERT + DEFAULT_EXTEND_INSERT
+        );
+        assert_eq!(
+            score(&scoring, "let x = f(a);", "let y = f(a b);") - base,
+            DEFAULT_START_INSERT
+        );
+        let scoring = scoring.with_insert_penalties(InsertPenalties::new(2., 0.5, 0.));
+        assert_eq!(score(&scoring, "a c", "a b c"), 2.);
+    }
+
+    /// Number of runs of consecutive operations which aren't matches.
+    fn edit_runs(scoring: &AffineScoring, left: &str, right: &str) -> usize {
+        let (left, right) = (Tokens::new(left), Tokens::new(right));
+        let alignment = align(scoring, &left.tokens, &right.tokens);
+        let changed: Vec<bool> = alignment
+            .operations()
+            .iter()
+            .map(|operation| match operation {
+                AlignmentOperation::Mutation { left, right } => left.text() != right.text(),
+                _ => true,
+            })
+            .collect();
+        (0..changed.len())
+            .filter(|&index| changed[index] && (index == 0 || !changed[index - 1]))
+            .count()
+    }
+
+    #[test]
+    fn match_extend_bonus_reduces_edit_runs() {
+        let (left, right) = ("(x()()x", "(f))(;(");
+        let scoring = AffineScoring::default();
+        assert_eq!(edit_runs(&scoring, left, right), 4);
+        let scoring = scoring.with_match_extend_bonus(0.3);
+        assert_eq!(edit_runs(&scoring, left, right), 2);
+    }
+
+    #[test]
+    fn match_extend_bonus_needs_previous_match() {
+        let scoring = AffineScoring::default().with_match_extend_bonus(0.5);
+        // Matches after a mismatch or at the start get no bonus, only the ones after them do.
+        assert_eq!(
+            score(&scoring, "a b", "c b"),
+            DEFAULT_MISMATCHED_TEXT_PENALTY
+        );
+        assert_eq!(
+            score(&scoring, "a b c", "d b c"),
+            DEFAULT_MISMATCHED_TEXT_PENALTY - 0.5
+        );
+        assert_eq!(score(&scoring, "a b c", "a b c"), -1.);
+    }
+}
@@ -1,38 +1,196 @@
+use std::borrow::Cow;
 use std::collections::VecDeque;
 
+use serde::Serialize;
+use unicode_width::UnicodeWidthChar;
+
 use crate::types::Token as TokenTrait;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TokenType {
     WhiteSpace,
     SpecialCharacter,
     Word,
+    /// Quoted string, including the quotes.
+    StringLiteral,
+    /// Number with an optional sign, fraction and exponent, like `-3.14e5`.
+    Number,
+    /// Whole comment, including its delimiters.
+    Comment,
+    /// Start of an indented block, with the nesting depth of the block (1 for the outermost one).
     BlockStart(usize),
+    /// End of an indented block, with the nesting depth of the block.
     BlockEnd(usize),
+    /// Whole sentence, with `Granularity::Sentence`.
+    Sentence,
+}
+
+/// Syntax of comments, which are then single `Comment` tokens.
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum CommentStyle {
+    /// `// line` and `/* block */` comments.
+    C,
+    /// `# line` comments.
+    Hash,
+    /// No comments, comment delimiters are ordinary tokens.
+    #[default]
+    None,
+}
+
+/// Unit of the tokens, i.e. of the diff.
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Granularity {
+    /// Words, numbers, whitespace and single special characters.
+    #[default]
+    Word,
+    /// Whole lines, including indentation and the line break, as `Word` tokens.
+    Line,
+    /// Every character (Unicode scalar value) is a token.
+    Char,
+    /// Sentences of prose, which end with `.`, `!` or `?` followed by whitespace, or before a
+    /// blank line. Sentences are paired by the words they share, and the word diff is done within
+    /// the pairs.
+    Sentence,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Token<'a, T> {
-    /// Text of the token
-    text: &'a str,
-    /// Index of the start of the token in the original text. End is defined by length of text.
+    /// Original text
+    source: &'a str,
+    /// Index of the start of the token in the original text.
     start: usize,
+    /// Index of the end of the token in the original text (exclusive). Same as start for
+    /// synthesized block tokens.
+    pub end: usize,
     // TODO: should this be a metadata, or even not in this type?
     pub t: T,
+    /// Set for tokens which should be left out of the alignment.
+    pub ignored: bool,
+    /// Line of the start of the token (1-based).
+    pub line: usize,
+    /// Column of the start of the token, in characters (0-based).
+    pub col: usize,
+    /// Lowercase text, computed once so comparing tokens case insensitively doesn't allocate.
+    pub(crate) lowercase: Cow<'a, str>,
+    /// Ids of the text and of the lowercase text, set by `Interner::intern_tokens`.
+    pub symbol: Option<Symbol>,
+}
+
+/// Interned ids of the text and of the lowercase text of a token, so comparing texts of tokens is
+/// comparing integers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Symbol {
+    pub text: u32,
+    pub lowercase: u32,
+}
+
+impl<'a, T: std::fmt::Debug> std::fmt::Debug for Token<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Token")
+            .field("text", &self.source.get(self.start..self.end).unwrap_or(""))
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("t", &self.t)
+            .field("ignored", &self.ignored)
+            .field("line", &self.line)
+            .field("col", &self.col)
+            .finish()
+    }
+}
+
+/// Serializable view of a token.
+#[derive(Serialize)]
+pub struct TokenView<'a, T> {
+    pub text: &'a str,
+    pub start: usize,
+    pub end: usize,
+    #[serde(rename = "type")]
+    pub t: &'a T,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl<'a, T> Token<'a, T> {
+    pub fn lowercase(&self) -> &str {
+        &self.lowercase
+    }
+
+    /// Text of the token, borrowed from the original text.
+    pub fn source_text(&self) -> &'a str {
+        self.source.get(self.start..self.end).unwrap_or("")
+    }
+
+    /// Whether the tokens have the same text. Interned tokens are compared by their symbols, so
+    /// both need to come from the same `Interner`.
+    pub fn same_text(&self, other: &Self) -> bool {
+        match (self.symbol, other.symbol) {
+            (Some(symbol), Some(other)) => symbol.text == other.text,
+            _ => self.source_text() == other.source_text(),
+        }
+    }
+
+    /// Whether the tokens have the same text ignoring case, comparing symbols like `same_text`.
+    pub fn same_lowercase(&self, other: &Self) -> bool {
+        match (self.symbol, other.symbol) {
+            (Some(symbol), Some(other)) => symbol.lowercase == other.lowercase,
+            _ => self.lowercase == other.lowercase,
+        }
+    }
+
+    pub fn view(&self) -> TokenView<'_, T> {
+        TokenView {
+            text: self.source.get(self.start..self.end).unwrap_or(""),
+            start: self.start,
+            end: self.end,
+            t: &self.t,
+            line: self.line,
+            col: self.col,
+        }
+    }
 }
 
 impl<'a> TokenTrait for Token<'a, TokenType> {
     fn text(&self) -> &str {
-        self.text
+        self.source_text()
     }
 
     fn start(&self) -> usize {
         self.start
     }
 
+    fn end(&self) -> usize {
+        self.end
+    }
+
     fn is_whitespace(&self) -> bool {
         self.t == TokenType::WhiteSpace
     }
+
+    fn is_block_start(&self) -> bool {
+        matches!(self.t, TokenType::BlockStart(_))
+    }
+
+    fn is_block_end(&self) -> bool {
+        matches!(self.t, TokenType::BlockEnd(_))
+    }
+
+    fn is_word(&self) -> bool {
+        matches!(
+            self.t,
+            TokenType::Word | TokenType::StringLiteral | TokenType::Number | TokenType::Sentence
+        )
+    }
+
+    fn is_ignored(&self) -> bool {
+        self.ignored
+    }
+
+    fn is_line_start(&self) -> bool {
+        let before =
+            self.source[..self.start].trim_end_matches(|x: char| x != '\n' && x.is_whitespace());
+        before.is_empty() || before.ends_with('\n')
+    }
 }
 
 #[derive(Debug)]
@@ -40,20 +198,259 @@ pub struct TokenParser<'a> {
     source: &'a str,
     position: usize,
     next_tokens: VecDeque<Token<'a, TokenType>>,
-    prev_indentation: usize,
+    /// Indentation levels of the open blocks, innermost last.
+    indentation: Vec<usize>,
+    /// Line and column of `position`.
+    line: usize,
+    col: usize,
+    /// Whether to emit `BlockStart` and `BlockEnd` tokens on indentation changes.
+    blocks: bool,
+    /// Whether quoted strings are single `StringLiteral` tokens.
+    string_literals: bool,
+    comments: CommentStyle,
+    /// Characters which are part of words in addition to alphanumeric ones and `_`.
+    word_chars: Vec<char>,
+    /// Characters which are always tokens of their own, brackets by default.
+    block_chars: Vec<char>,
+    granularity: Granularity,
+    /// Display width of a tab, for measuring indentation.
+    tab_width: usize,
 }
 
 impl<'a> TokenParser<'a> {
+    /// Iterator over the tokens of `text`, usable as a lexer without the aligner. Tokens come in
+    /// order and cover the whole text without gaps, `start()..end()` being the absolute byte
+    /// range of each. Block tokens have empty ranges.
     pub fn parse(text: &'a str) -> TokenParser<'a> {
         TokenParser {
             source: text,
             position: 0,
             next_tokens: VecDeque::new(),
-            prev_indentation: 0,
+            indentation: vec![],
+            line: 1,
+            col: 0,
+            blocks: true,
+            string_literals: false,
+            comments: CommentStyle::None,
+            word_chars: vec![],
+            block_chars: DEFAULT_BLOCK_CHARS.chars().collect(),
+            granularity: Granularity::Word,
+            tab_width: 8,
+        }
+    }
+
+    /// Don't emit block tokens, indentation is then just whitespace.
+    pub fn without_blocks(mut self) -> Self {
+        self.blocks = false;
+        self
+    }
+
+    /// Emit strings in `"` or `'` quotes as single tokens.
+    pub fn with_string_literals(mut self) -> Self {
+        self.string_literals = true;
+        self
+    }
+
+    /// Emit comments of the given style as single tokens.
+    pub fn with_comments(mut self, comments: CommentStyle) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Treats the given characters as word characters, e.g. `-` for kebab-case identifiers.
+    pub fn with_word_chars(mut self, word_chars: &str) -> Self {
+        self.word_chars = word_chars.chars().collect();
+        self
+    }
+
+    /// Sets the characters which are single character tokens instead of being part of runs of
+    /// special characters. Empty string makes brackets ordinary special characters.
+    pub fn with_block_chars(mut self, block_chars: &str) -> Self {
+        self.block_chars = block_chars.chars().collect();
+        self
+    }
+
+    /// Sets the unit of tokens. Block tokens are emitted only with `Granularity::Word`.
+    pub fn with_granularity(mut self, granularity: Granularity) -> Self {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Sets the display width of a tab used for indentation levels (default 8).
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+}
+
+/// Display column after `indentation`, with tabs advancing to the next multiple of `tab_width`.
+/// Carriage returns don't take a column, so CRLF line endings don't change indentation levels.
+fn indentation_width(indentation: &str, tab_width: usize) -> usize {
+    indentation.chars().fold(0, |column, c| match c {
+        '\t' => column + tab_width - column % tab_width,
+        '\r' => column,
+        _ => column + 1,
+    })
+}
+
+/// Length of the string literal at the start of `text`, which starts with the quote. Quotes
+/// escaped with a backslash don't end the literal. An unterminated literal ends at the end of the
+/// line.
+fn string_literal_len(text: &str) -> usize {
+    let Some(quote) = text.chars().next() else {
+        return 0;
+    };
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        if c == '\n' {
+            return index;
+        } else if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return index + c.len_utf8();
+        }
+    }
+    text.len()
+}
+
+/// Length of the line at the start of `text`, including the line break.
+fn line_len(text: &str) -> usize {
+    text.find('\n').map(|end| end + 1).unwrap_or(text.len())
+}
+
+/// Words before a `.` which don't end a sentence, in lowercase.
+const ABBREVIATIONS: &[&str] = &[
+    "cf", "dr", "eg", "fig", "ie", "jr", "mr", "mrs", "ms", "no", "prof", "sr", "st", "vs",
+];
+
+/// Whether the `.` after `before` ends a sentence, which continues with `after` following the
+/// whitespace. Best effort: abbreviations like `e.g.`, initials, numbers of list items and a
+/// lowercase continuation don't end one.
+fn ends_sentence(before: &str, after: &str) -> bool {
+    let word_start = before
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !c.is_alphanumeric() && c != '.')
+        .map(|(index, c)| index + c.len_utf8())
+        .unwrap_or(0);
+    let word = &before[word_start..];
+    let abbreviation = word.contains('.')
+        || word.chars().count() == 1
+        || word.chars().all(|c| c.is_ascii_digit())
+        || ABBREVIATIONS.contains(&word.to_lowercase().as_str());
+    !abbreviation && !after.starts_with(char::is_lowercase)
+}
+
+/// Length of the sentence at the start of `text`, which doesn't start with whitespace. It ends
+/// after `.`, `!` or `?` (and closing quotes or brackets) followed by whitespace, before a blank
+/// line, or at the end of the text, without the whitespace after it.
+fn sentence_len(text: &str) -> usize {
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c == '\n' {
+            let next_line = &text[index + 1..];
+            let blank_line = next_line[..line_len(next_line)].trim().is_empty();
+            if blank_line {
+                return text[..index].trim_end().len();
+            }
+        }
+        if !matches!(c, '.' | '!' | '?') {
+            continue;
+        }
+        let mut end = index + c.len_utf8();
+        while let Some(&(index, c)) = chars.peek() {
+            if !matches!(
+                c,
+                '.' | '!' | '?' | '"' | '\'' | ')' | ']' | '”' | '’' | '»'
+            ) {
+                break;
+            }
+            end = index + c.len_utf8();
+            chars.next();
+        }
+        let after = &text[end..];
+        if after.starts_with(char::is_whitespace)
+            && (c != '.' || ends_sentence(&text[..index], after.trim_start()))
+        {
+            return end;
         }
     }
+    text.trim_end().len()
 }
 
+/// Length of the comment at the start of `text`, or `None` if the text doesn't start with one.
+/// Line comments end before the line break, an unterminated block comment at the end of the text.
+fn comment_len(style: CommentStyle, text: &str) -> Option<usize> {
+    let line_len = || text.find('\n').unwrap_or(text.len());
+    match style {
+        CommentStyle::C if text.starts_with("//") => Some(line_len()),
+        CommentStyle::C if text.starts_with("/*") => Some(
+            text[2..]
+                .find("*/")
+                .map(|end| end + 4)
+                .unwrap_or(text.len()),
+        ),
+        CommentStyle::Hash if text.starts_with('#') => Some(line_len()),
+        _ => None,
+    }
+}
+
+/// Length of the number at the start of `text`, which follows `before` in the source: an
+/// optional sign, digits, an optional fraction and an optional exponent. `None` if the text
+/// doesn't start with a number, or if the number continues with word characters (like `0x1f`).
+/// The sign is part of the number only if it can't be a binary operator, i.e. it doesn't follow a
+/// word or a closing bracket. In dotted sequences like the version `1.2.3` the parts are separate
+/// integers instead of `1.2` followed by `.3`.
+fn number_len(before: &str, text: &str, word_chars: &[char]) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        bytes[from.min(bytes.len())..]
+            .iter()
+            .take_while(|x| x.is_ascii_digit())
+            .count()
+    };
+    let after_operand = before
+        .chars()
+        .next_back()
+        .map(|c| char_type(c, word_chars, &[]) == CharType::Word || matches!(c, ')' | ']' | '}'))
+        .unwrap_or(false);
+    let mut len = usize::from(matches!(bytes.first(), Some(b'+' | b'-')) && !after_operand);
+    let integer = digits(len);
+    if integer == 0 {
+        return None;
+    }
+    len += integer;
+    let dotted = |from: usize| bytes.get(from) == Some(&b'.') && digits(from + 1) > 0;
+    let in_version = before.ends_with('.')
+        && before[..before.len() - 1]
+            .chars()
+            .next_back()
+            .map(|c| c.is_ascii_digit())
+            .unwrap_or(false);
+    if dotted(len) && !in_version {
+        let fraction_end = len + 1 + digits(len + 1);
+        if !dotted(fraction_end) {
+            len = fraction_end;
+        }
+    }
+    if let Some(b'e' | b'E') = bytes.get(len) {
+        let sign = usize::from(matches!(bytes.get(len + 1), Some(b'+' | b'-')));
+        let exponent = digits(len + 1 + sign);
+        if exponent > 0 {
+            len += 1 + sign + exponent;
+        }
+    }
+    match text[len..].chars().next() {
+        Some(c) if char_type(c, word_chars, &[]) == CharType::Word || is_combining(c) => None,
+        _ => Some(len),
+    }
+}
+
+/// Characters which are tokens of their own unless configured otherwise.
+pub const DEFAULT_BLOCK_CHARS: &str = "()[]{}";
+
 #[derive(PartialEq, Debug)]
 enum CharType {
     WhiteSpace,
@@ -62,12 +459,20 @@ enum CharType {
     Other,
 }
 
-fn char_type(c: char) -> CharType {
+/// Zero width characters (combining marks, joiners) belong to the token of the preceding
+/// character, so tokens never split a grapheme.
+fn is_combining(c: char) -> bool {
+    !c.is_whitespace() && c.width() == Some(0)
+}
+
+/// Class of the character, with `word_chars` being word characters in addition to alphanumeric
+/// ones and `_`, and `block_chars` the characters which are tokens of their own.
+fn char_type(c: char, word_chars: &[char], block_chars: &[char]) -> CharType {
     if c.is_whitespace() {
         CharType::WhiteSpace
-    } else if c.is_alphanumeric() || c == '_' {
+    } else if c.is_alphanumeric() || c == '_' || word_chars.contains(&c) {
         CharType::Word
-    } else if c == '(' || c == ')' || c == '[' || c == ']' || c == '{' || c == '}' {
+    } else if block_chars.contains(&c) {
         CharType::BlockChar
     } else {
         CharType::Other
@@ -82,8 +487,45 @@ impl<'a> Iterator for TokenParser<'a> {
             return Some(t);
         }
         let rest_of_text = self.source.split_at(self.position).1;
-        let c_type = char_type(rest_of_text.chars().next()?);
-        let len = if c_type == CharType::BlockChar {
+        let first = rest_of_text.chars().next()?;
+        // Length of the token when it is a whole line or sentence.
+        let unit = match self.granularity {
+            Granularity::Line => Some(line_len(rest_of_text)),
+            Granularity::Sentence if !first.is_whitespace() => Some(sentence_len(rest_of_text)),
+            _ => None,
+        };
+        let c_type = match unit {
+            Some(_) => CharType::Word,
+            None => char_type(first, &self.word_chars, &self.block_chars),
+        };
+        let char = self.granularity == Granularity::Char;
+        let string_literal =
+            unit.is_none() && !char && self.string_literals && (first == '"' || first == '\'');
+        let comment = if string_literal || unit.is_some() || char {
+            None
+        } else {
+            comment_len(self.comments, rest_of_text)
+        };
+        let number = if string_literal || comment.is_some() || unit.is_some() || char {
+            None
+        } else {
+            number_len(
+                &self.source[..self.position],
+                rest_of_text,
+                &self.word_chars,
+            )
+        };
+        let len = if let Some(len) = unit {
+            len
+        } else if char {
+            first.len_utf8()
+        } else if string_literal {
+            string_literal_len(rest_of_text)
+        } else if let Some(len) = comment {
+            len
+        } else if let Some(len) = number {
+            len
+        } else if c_type == CharType::BlockChar {
             rest_of_text
                 .chars()
                 .next()
@@ -92,43 +534,165 @@ impl<'a> Iterator for TokenParser<'a> {
         } else {
             rest_of_text
                 .chars()
-                .take_while(|x| char_type(*x) == c_type)
+                .take_while(|x| {
+                    char_type(*x, &self.word_chars, &self.block_chars) == c_type
+                        || (c_type != CharType::WhiteSpace && is_combining(*x))
+                })
                 .map(|x| x.len_utf8())
                 .sum::<usize>()
         };
         let start = self.position;
         let end = self.position + len;
+        let text = &rest_of_text[..len];
+        let lowercase = text.to_lowercase();
         let token = Token {
-            text: self.source.get(start..end).unwrap(), // This should never fail
+            source: self.source,
             start,
+            end,
             t: match c_type {
+                _ if string_literal => TokenType::StringLiteral,
+                _ if comment.is_some() => TokenType::Comment,
+                _ if number.is_some() => TokenType::Number,
+                _ if unit.is_some() && self.granularity == Granularity::Sentence => {
+                    TokenType::Sentence
+                }
                 CharType::WhiteSpace => TokenType::WhiteSpace,
                 CharType::Word => TokenType::Word,
                 CharType::Other => TokenType::SpecialCharacter,
                 CharType::BlockChar => TokenType::SpecialCharacter,
             },
+            ignored: false,
+            line: self.line,
+            col: self.col,
+            lowercase: if lowercase == text {
+                Cow::Borrowed(text)
+            } else {
+                Cow::Owned(lowercase)
+            },
+            symbol: None,
         };
-        self.position += len;
-        if c_type == CharType::WhiteSpace {
-            let whitespace_text = self.source.get(self.position - len..self.position).unwrap();
-            let current_indentation = if whitespace_text.contains('\n') {
-                whitespace_text.split('\n').last().unwrap().len()
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 0;
             } else {
-                self.prev_indentation
+                self.col += 1;
+            }
+        }
+        self.position += len;
+        if c_type == CharType::WhiteSpace && self.blocks && self.granularity == Granularity::Word {
+            // Indentation is the whitespace after the last line break.
+            let Some((_, indentation)) = text.rsplit_once('\n') else {
+                return Some(token);
+            };
+            let current_indentation = indentation_width(indentation, self.tab_width);
+            let block_token = |t| Token {
+                source: self.source,
+                start: self.position,
+                end: self.position,
+                t,
+                ignored: false,
+                // Same position as the whitespace which caused the block change.
+                line: token.line,
+                col: token.col,
+                lowercase: Cow::Borrowed(""),
+                symbol: None,
             };
-            if current_indentation != self.prev_indentation {
-                self.next_tokens.push_back(Token {
-                    text: self.source.get(self.position..self.position).unwrap(), // This should never fail
-                    start: self.position,
-                    t: if current_indentation < self.prev_indentation {
-                        TokenType::BlockEnd(self.prev_indentation)
-                    } else {
-                        TokenType::BlockStart(current_indentation)
-                    },
-                });
-                self.prev_indentation = current_indentation;
+            // A dedent closes every block indented more than the new line.
+            while let Some(&level) = self.indentation.last() {
+                if level <= current_indentation {
+                    break;
+                }
+                self.next_tokens
+                    .push_back(block_token(TokenType::BlockEnd(self.indentation.len())));
+                self.indentation.pop();
+            }
+            // An indentation between two open levels starts a new block as well.
+            if current_indentation > self.indentation.last().copied().unwrap_or(0) {
+                self.indentation.push(current_indentation);
+                self.next_tokens
+                    .push_back(block_token(TokenType::BlockStart(self.indentation.len())));
             }
         }
         Some(token)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Parsers with different options, as they take different paths through the text.
+    fn parsers(text: &str) -> Vec<TokenParser<'_>> {
+        vec![
+            TokenParser::parse(text),
+            TokenParser::parse(text).without_blocks(),
+            TokenParser::parse(text)
+                .with_string_literals()
+                .with_comments(CommentStyle::C),
+            TokenParser::parse(text)
+                .with_comments(CommentStyle::Hash)
+                .with_word_chars("-é")
+                .with_block_chars("")
+                .with_tab_width(3),
+            TokenParser::parse(text).with_granularity(Granularity::Line),
+            TokenParser::parse(text).with_granularity(Granularity::Char),
+            TokenParser::parse(text).with_granularity(Granularity::Sentence),
+        ]
+    }
+
+    fn assert_tokens_cover(text: &str) {
+        for parser in parsers(text) {
+            let mut end = 0;
+            let mut concatenated = String::new();
+            for token in parser {
+                assert_eq!(token.start(), end);
+                assert_eq!(token.text(), &text[token.start()..token.end()]);
+                if token.is_block_start() || token.is_block_end() {
+                    assert!(token.text().is_empty());
+                }
+                end = token.end();
+                concatenated.push_str(token.text());
+            }
+            assert_eq!(concatenated, text);
+        }
+    }
+
+    #[test]
+    fn dedent_closes_every_level() {
+        let text =
+            "def f(x):\n    for a in x:\n        if a:\n            print(a)\n    return x\n";
+        let blocks: Vec<_> = TokenParser::parse(text)
+            .filter(|token| token.is_block_start() || token.is_block_end())
+            .map(|token| (token.start(), token.t))
+            .collect();
+        let (print, ret) = (text.find("print").unwrap(), text.find("return").unwrap());
+        // Dedenting to the body of the function closes the `if` and the `for` blocks.
+        assert_eq!(
+            blocks,
+            [
+                (text.find("for").unwrap(), TokenType::BlockStart(1)),
+                (text.find("if").unwrap(), TokenType::BlockStart(2)),
+                (print, TokenType::BlockStart(3)),
+                (ret, TokenType::BlockEnd(3)),
+                (ret, TokenType::BlockEnd(2)),
+                (text.len(), TokenType::BlockEnd(1)),
+            ]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn tokens_cover_any_text(text in any::<String>()) {
+            assert_tokens_cover(&text);
+        }
+
+        /// Mostly indentation, quotes, comment delimiters and multi-byte characters.
+        #[test]
+        fn tokens_cover_code_like_text(text in "[ \t\r\n\"'#/*\\\\(){}.,!?a-z0-9_é😀你\u{301}-]*") {
+            assert_tokens_cover(&text);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of characters and `?` any
+/// single character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // `matches[p]` is whether `pattern[..p]` matches the prefix of `name` seen so far.
+    let mut matches = vec![false; pattern.len() + 1];
+    matches[0] = true;
+    for p in 0..pattern.len() {
+        matches[p + 1] = matches[p] && pattern[p] == '*';
+    }
+    for c in name {
+        let mut next = vec![false; pattern.len() + 1];
+        for p in 0..pattern.len() {
+            next[p + 1] = match pattern[p] {
+                '*' => next[p] || matches[p + 1],
+                '?' => matches[p],
+                x => matches[p] && x == c,
+            };
+        }
+        matches = next;
+    }
+    matches[pattern.len()]
+}
+
+/// Paths of all files under `root`, relative to it. Files and directories whose name matches one
+/// of the `exclude` globs are skipped.
+pub fn files(root: &Path, exclude: &[String]) -> std::io::Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut directories = vec![PathBuf::new()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(root.join(&directory))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if exclude
+                .iter()
+                .any(|pattern| glob_match(pattern, &name.to_string_lossy()))
+            {
+                continue;
+            }
+            let path = directory.join(&name);
+            if entry.file_type()?.is_dir() {
+                directories.push(path);
+            } else {
+                files.insert(path);
+            }
+        }
+    }
+    Ok(files)
+}
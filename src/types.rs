@@ -1,10 +1,41 @@
 pub trait AlignmentScoring<T> {
     fn insert_score(&self, inserted: &T, previous_is_same: bool) -> f64;
-    fn mutation_score(&self, left: &T, right: &T) -> f64;
+    /// Cost of inserting `inserted` as the `run_length`-th token (starting at 1) of a run of
+    /// inserts on the same side, which allows gap costs other than affine ones. The aligners keep
+    /// only the run length of the best path into each cell, so for costs which get cheaper with
+    /// longer runs the alignment is good but not guaranteed to be the best. Defaults to the affine
+    /// costs of `insert_score`.
+    fn insert_run_score(&self, inserted: &T, run_length: usize) -> f64 {
+        self.insert_score(inserted, run_length > 1)
+    }
+    /// Cost of aligning `left` with `right`, where `previous_is_same` tells whether the previous
+    /// operation is a match, i.e. a mutation costing nothing. Like run lengths, the aligners keep
+    /// this only for the best path into each cell.
+    fn mutation_score(&self, left: &T, right: &T, previous_is_same: bool) -> f64;
+    /// Lower bound of `mutation_score` over all pairs of tokens, for bounding costs of paths the
+    /// aligners don't compute. Defaults to 0, as costs aren't negative.
+    fn min_mutation_score(&self) -> f64 {
+        0.
+    }
 }
 
 pub trait Token {
     fn text(&self) -> &str;
     fn start(&self) -> usize;
+    /// Byte offset after the end of the token in the original text.
+    fn end(&self) -> usize {
+        self.start() + self.text().len()
+    }
     fn is_whitespace(&self) -> bool;
+    fn is_block_start(&self) -> bool;
+    fn is_block_end(&self) -> bool;
+    fn is_word(&self) -> bool;
+    /// Ignored tokens are left out of the alignment like whitespace, and rendered as unchanged.
+    fn is_ignored(&self) -> bool {
+        false
+    }
+    /// Whether the token is the first one on its line.
+    fn is_line_start(&self) -> bool {
+        false
+    }
 }
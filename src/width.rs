@@ -0,0 +1,40 @@
+/// Width used when nothing else is known, e.g. when the output is piped.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Resolves the output width: the explicit width if given, else `$COLUMNS`, else the width of the
+/// terminal, else `DEFAULT_WIDTH`.
+pub fn output_width(explicit: Option<usize>) -> usize {
+    resolve(
+        explicit,
+        std::env::var("COLUMNS").ok().as_deref(),
+        terminal_size::terminal_size().map(|(width, _)| width.0 as usize),
+    )
+}
+
+fn resolve(explicit: Option<usize>, columns: Option<&str>, terminal: Option<usize>) -> usize {
+    explicit
+        .or_else(|| columns.and_then(|x| x.trim().parse().ok()))
+        .filter(|&x| x > 0)
+        .or(terminal)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(resolve(Some(100), Some("60"), Some(120)), 100);
+        assert_eq!(resolve(None, Some("60"), Some(120)), 60);
+        assert_eq!(resolve(None, Some(" 60\n"), None), 60);
+        assert_eq!(resolve(None, None, Some(120)), 120);
+        assert_eq!(resolve(None, None, None), DEFAULT_WIDTH);
+    }
+
+    #[test]
+    fn invalid_widths_are_skipped() {
+        assert_eq!(resolve(None, Some("wide"), Some(120)), 120);
+        assert_eq!(resolve(None, Some("0"), None), DEFAULT_WIDTH);
+    }
+}
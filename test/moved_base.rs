@@ -0,0 +1,12 @@
+fn helper(items: &[i32]) -> i32 {
+    items.iter().map(|x| x * 2).sum()
+}
+
+fn main() {
+    let items = vec![1, 2, 3];
+    println!("{}", helper(&items));
+}
+
+fn other() -> bool {
+    true
+}
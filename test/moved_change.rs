@@ -0,0 +1,12 @@
+fn main() {
+    let items = vec![1, 2, 3, 4];
+    println!("{}", helper(&items));
+}
+
+fn other() -> bool {
+    false
+}
+
+fn helper(items: &[i32]) -> i32 {
+    items.iter().map(|x| x * 2).sum()
+}
@@ -0,0 +1,10 @@
+def outer(items):
+    for item in items:
+        if item:
+            while item > 0:
+                item -= 1
+    return items
+
+
+def other():
+    return 1
@@ -0,0 +1,10 @@
+def outer(items):
+    for item in items:
+        if item:
+            while item > 1:
+                item -= 2
+    return sorted(items)
+
+
+def other():
+    return 2
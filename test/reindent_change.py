@@ -0,0 +1,8 @@
+def process(items):
+    for item in items:
+        if item > 0:
+            total = item * 3
+            print(total)
+        else:
+            print("skip")
+    return items
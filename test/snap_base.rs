@@ -0,0 +1,3 @@
+if x {
+    f(a);
+}
@@ -0,0 +1,6 @@
+if x {
+    f(a);
+}
+if x {
+    f(a);
+}
@@ -0,0 +1,304 @@
+use std::process::{Command, Output};
+
+/// Runs the binary with `args` from the root of the crate, so paths of fixtures are relative to
+/// it.
+fn run(args: &[&str]) -> Output {
+    run_with_env(args, &[])
+}
+
+/// Same as `run`, with environment variables `env` set.
+fn run_with_env(args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_platypus-diff"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_remove("NO_COLOR")
+        .env_remove("COLUMNS")
+        .envs(env.iter().copied())
+        .output()
+        .expect("failed to run the binary")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn pretty_diff_of_fixtures() {
+    let output = run(&["--no-color", "test/base.rs", "test/change.rs"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), include_str!("../test/base_change.pretty"));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn identical_files_exit_with_zero() {
+    let output = run(&["--quiet", "test/base.rs", "test/base.rs"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn line_endings_are_equal_with_strip_cr() {
+    let files = ["test/crlf_base.py", "test/crlf_change.py"];
+    let output = run(&[&["--format", "unified"], &files[..]].concat());
+    assert!(
+        !output.stdout.is_empty(),
+        "fixtures should differ in line endings"
+    );
+    let output = run(&[&["--strip-cr", "--format", "unified"], &files[..]].concat());
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn invalid_utf8_is_a_clear_error() {
+    let output = run(&["test/latin1.rs", "test/base.rs"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: test/latin1.rs: not valid UTF-8 (invalid byte at offset 29); re-encode it as \
+         UTF-8 or use --lossy-utf8\n"
+    );
+}
+
+#[test]
+fn lossy_utf8_replaces_invalid_bytes() {
+    let output = run(&[
+        "--lossy-utf8",
+        "--color",
+        "never",
+        "test/latin1.rs",
+        "test/latin1.rs",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("println!(\"caf\u{fffd}\");"));
+}
+
+/// Outputs of GNU `diff` (which defaults to the normal format) are stored next to the fixtures.
+#[test]
+fn normal_format_matches_gnu_diff() {
+    for (left, right, expected) in [
+        (
+            "test/nested_base.py",
+            "test/nested_change.py",
+            include_str!("../test/nested.normal"),
+        ),
+        (
+            "test/reindent_base.py",
+            "test/reindent_change.py",
+            include_str!("../test/reindent.normal"),
+        ),
+    ] {
+        let output = run(&["--format", "normal", "--color", "never", left, right]);
+        assert_eq!(stdout(&output), expected, "{} {}", left, right);
+    }
+}
+
+/// The cleanup moves the inserted block after the closing brace of the first one, instead of
+/// splitting the closing brace from its block.
+#[test]
+fn cleanup_snaps_inserts_to_boundaries() {
+    let files = ["test/snap_base.rs", "test/snap_change.rs"];
+    let output = run(&[&["--no-color"], &files[..]].concat());
+    assert_eq!(stdout(&output), include_str!("../test/snap.pretty"));
+    let output = run(&[&["--no-color", "--cleanup", "boundaries"], &files[..]].concat());
+    assert_eq!(
+        stdout(&output),
+        include_str!("../test/snap_boundaries.pretty")
+    );
+}
+
+/// Applies an `ed` script with the commands of `--format ed` (`a`, `c`, `d` and `s/.//`) to
+/// `text`.
+fn apply_ed_script(text: &str, script: &str) -> String {
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    // Current line (1-based), the last one added or changed.
+    let mut current = 0;
+    let mut commands = script.lines();
+    while let Some(command) = commands.next() {
+        if command == "s/.//" {
+            lines[current - 1].remove(0);
+            continue;
+        }
+        let (range, name) = command.split_at(command.len() - 1);
+        let (start, end) = match range.split_once(',') {
+            Some((start, end)) => (start.parse().unwrap(), end.parse().unwrap()),
+            None if range.is_empty() => (current, current),
+            None => (range.parse().unwrap(), range.parse().unwrap()),
+        };
+        let at = match name {
+            "a" => end,
+            "c" | "d" => {
+                lines.drain(start - 1..end);
+                start - 1
+            }
+            _ => panic!("unexpected command {:?}", command),
+        };
+        if name == "d" {
+            current = at;
+            continue;
+        }
+        let added: Vec<String> = commands
+            .by_ref()
+            .take_while(|line| *line != ".")
+            .map(String::from)
+            .collect();
+        current = at + added.len();
+        lines.splice(at..at, added);
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[test]
+fn ed_script_turns_left_into_right() {
+    for (left, right) in [
+        ("test/base.rs", "test/change.rs"),
+        ("test/moved_base.rs", "test/moved_change.rs"),
+        ("test/nested_base.py", "test/nested_change.py"),
+        ("test/snap_base.rs", "test/snap_change.rs"),
+        ("test/change.rs", "test/base.rs"),
+    ] {
+        let output = run(&["--format", "ed", left, right]);
+        let read =
+            |path| std::fs::read_to_string(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path));
+        let (left_text, right_text) = (read(left).unwrap(), read(right).unwrap());
+        assert_eq!(
+            apply_ed_script(&left_text, &stdout(&output)),
+            right_text,
+            "{} {}",
+            left,
+            right
+        );
+    }
+}
+
+#[test]
+fn ed_script_escapes_lone_dots() {
+    let output = run(&[
+        "--format",
+        "ed",
+        "--left-text",
+        "a\n",
+        "--right-text",
+        "a\n.\nb\n",
+    ]);
+    assert_eq!(apply_ed_script("a\n", &stdout(&output)), "a\n.\nb\n");
+}
+
+#[test]
+fn width_flag_overrides_columns() {
+    let stat = |args: &[&str], env: &[(&str, &str)]| {
+        let args = [args, &["--stat", "test/base.rs", "test/change.rs"]].concat();
+        stdout(&run_with_env(&args, env))
+    };
+    let default = stat(&[], &[]);
+    let narrow = stat(&[], &[("COLUMNS", "40")]);
+    let wide = stat(&["--width", "60"], &[]);
+    assert_ne!(narrow, default);
+    assert_ne!(wide, default);
+    assert_eq!(stat(&["--width", "40"], &[]), narrow);
+    assert_eq!(stat(&["--width", "60"], &[("COLUMNS", "40")]), wide);
+    // Output is not a terminal, so the default width is used.
+    assert_eq!(stat(&["--width", "80"], &[]), default);
+}
+
+#[test]
+fn side_by_side_columns_line_up_with_wide_characters() {
+    use unicode_width::UnicodeWidthStr;
+
+    let output = run(&[
+        "--color",
+        "never",
+        "--side-by-side",
+        "--width",
+        "60",
+        "test/wide_base.txt",
+        "test/wide_change.txt",
+    ]);
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().filter(|line| !line.is_empty()).collect();
+    assert_eq!(lines.len(), 3);
+    for line in lines {
+        let separator = line.find(" | ").unwrap();
+        assert_eq!(line[..separator].width(), 28, "{:?}", line);
+    }
+}
+
+#[test]
+fn color_words_shows_ignored_changes_as_unchanged() {
+    let color_words = |args: &[&str]| {
+        let args = [
+            &["--format", "color-words", "--color", "always"],
+            args,
+            &[
+                "--left-text",
+                "Hello 123 world\n",
+                "--right-text",
+                "hello 456 World\n",
+            ],
+        ]
+        .concat();
+        stdout(&run(&args))
+    };
+    assert!(color_words(&[]).contains('\x1b'));
+    assert_eq!(
+        color_words(&["-i"]),
+        "hello \x1b[9;31m123\x1b[0m\x1b[32m456\x1b[0m World\n"
+    );
+    assert_eq!(
+        color_words(&["-i", "--ignore-matching", "^[0-9]+$"]),
+        "hello 456 World\n"
+    );
+}
+
+/// Traced paths used to share their prefixes and warn on stderr when they had to be copied.
+#[test]
+fn large_diff_prints_nothing_to_stderr() {
+    let text = |changed: usize| {
+        (0..250)
+            .map(|line| {
+                let value = if line % changed == 0 { line * 3 } else { line };
+                format!(
+                    "    let value_{} = compute({}, \"{}\");\n",
+                    line,
+                    value,
+                    line % 7
+                )
+            })
+            .collect::<String>()
+    };
+    let (left, right) = (text(usize::MAX), text(9));
+    // Without line anchors, the whole inputs are aligned at once.
+    let output = run(&[
+        "--no-color",
+        "--line-anchors",
+        "never",
+        "--left-text",
+        &left,
+        "--right-text",
+        &right,
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("compute(27, "));
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn squeeze_blank_collapses_inserted_blank_lines() {
+    let args = [
+        "--no-color",
+        "--left-text",
+        "a\nb\n",
+        "--right-text",
+        "a\n\n\n\nb\n",
+    ];
+    let output = run(&args);
+    assert_eq!(stdout(&output), "  a\n  \n  \n  \n  b\n  \n");
+    let output = run(&[&["--squeeze-blank"], &args[..]].concat());
+    assert_eq!(stdout(&output), "  a\n  \n  b\n  \n");
+}